@@ -0,0 +1,88 @@
+//! Times the matchers over a large list of paths.
+//!
+//! Run it with `cargo bench --bench matches`.
+
+#![allow(dead_code, unused_imports)]
+// The modules keep the idioms of rumenu, see main.rs
+#![allow(clippy::redundant_field_names, clippy::len_zero, clippy::ptr_arg, clippy::redundant_static_lifetimes,
+         clippy::partialeq_to_none, clippy::comparison_to_empty, clippy::useless_vec, clippy::cmp_owned)]
+
+extern crate libc;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+#[path = "../src/matches.rs"]
+mod matches;
+#[path = "../src/pool.rs"]
+mod pool;
+
+use matches::{score_items, sort_scored, CaseMode, Matcher, MatcherRegistry};
+use pool::MatchPool;
+
+const ITEMS: usize = 200000;
+const ROUNDS: u32 = 5;
+const QUERIES: &'static [&'static str] = &["s", "src", "src/ma", "SRC mod"];
+
+/// Paths made of a few common words, always the same ones.
+fn items() -> Vec<String> {
+    let words = ["src", "lib", "main", "mod", "test", "bin", "usr", "share", "doc", "matches", "ui", "x11"];
+    let mut seed: u64 = 42;
+    let mut next = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as usize
+    };
+    (0..ITEMS).map(|n| {
+        let depth = 2 + next() % 4;
+        let parts: Vec<&str> = (0..depth).map(|_| words[next() % words.len()]).collect();
+        format!("/{}-{}.rs", parts.join("/"), n)
+    }).collect()
+}
+
+/// Ranks every item on the calling thread, the baseline for the pool.
+fn rank_sequential(matcher: &dyn Matcher, text: &String, items: &[String]) -> Vec<usize> {
+    let candidates: Vec<usize> = (0..items.len()).collect();
+    let mut scored = score_items(matcher, text, items, &candidates, CaseMode::Smart);
+    sort_scored(&mut scored);
+    scored.into_iter().map(|(n, _)| n).collect()
+}
+
+/// Average milliseconds of `ROUNDS` runs of `f`.
+fn time<F: FnMut() -> usize>(mut f: F) -> (f64, usize) {
+    let start = Instant::now();
+    let mut found = 0;
+    for _ in 0..ROUNDS {
+        found = f();
+    }
+    let elapsed = start.elapsed();
+    let millis = elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1e6;
+    (millis / ROUNDS as f64, found)
+}
+
+fn main() {
+    let items = Arc::new(items());
+    let pool = MatchPool::with_cpus();
+    let registry = MatcherRegistry::new();
+
+    for name in registry.names() {
+        let matcher: Arc<dyn Matcher> = Arc::from(MatcherRegistry::new().take(name).unwrap());
+        for text in QUERIES {
+            let text = text.to_string();
+            let (sequential, found) = time(|| rank_sequential(&*matcher, &text, &items).len());
+            let (parallel, _) = time(|| {
+                let candidates = (0..items.len()).collect();
+                pool.rank(&matcher, &text, &items, candidates, CaseMode::Smart, &|| false).unwrap().len()
+            });
+            println!("{:6} {:10} {:7} matches  sequential {:8.2} ms  pool {:8.2} ms", name, format!("{:?}", text), found, sequential, parallel);
+        }
+
+        // Typing "/ma" after "src" only looks at the matches of "src"
+        let previous: Vec<usize> = rank_sequential(&*matcher, &"src".to_string(), &items);
+        let mut previous_sorted = previous.clone();
+        previous_sorted.sort();
+        let (narrowed, found) = time(|| {
+            pool.rank(&matcher, &"src/ma".to_string(), &items, previous_sorted.clone(), CaseMode::Smart, &|| false).unwrap().len()
+        });
+        println!("{:6} {:10} {:7} matches  narrowed from {} matches {:8.2} ms", name, "\"src/ma\"", found, previous.len(), narrowed);
+    }
+}
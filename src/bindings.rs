@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+
+use x11::xlib;
+use x11::keysym;
+
+/// Named actions that key chords can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    CursorLeft,
+    CursorRight,
+    CursorStart,
+    CursorEnd,
+    /// Moves the cursor or the selection to the left, like dmenu does with Left.
+    Left,
+    /// Moves the cursor or the selection to the right, like dmenu does with Right.
+    Right,
+    /// Selects the first match, or moves the cursor to the start when it already is.
+    Home,
+    /// Moves the cursor to the end, or selects the last match when it already is.
+    End,
+    DeleteLeft,
+    DeleteRight,
+    DeleteWord,
+    DeleteToStart,
+    DeleteToEnd,
+    /// Walks the history on an empty query like a shell does, otherwise selects the previous match.
+    Up,
+    /// Walks the history back towards the empty query, otherwise selects the next match.
+    Down,
+    SelectPrev,
+    SelectNext,
+    SelectFirst,
+    SelectLast,
+    PagePrev,
+    PageNext,
+    Complete,
+    /// Marks or unmarks the selected item in multi-select mode.
+    ToggleMark,
+    Accept,
+    AcceptInput,
+    Cancel,
+    PastePrimary,
+    PasteClipboard,
+}
+
+const ACTION_NAMES: &'static [(&'static str, Action)] = &[
+    ("cursor-left", Action::CursorLeft),
+    ("cursor-right", Action::CursorRight),
+    ("cursor-start", Action::CursorStart),
+    ("cursor-end", Action::CursorEnd),
+    ("left", Action::Left),
+    ("right", Action::Right),
+    ("home", Action::Home),
+    ("end", Action::End),
+    ("delete-left", Action::DeleteLeft),
+    ("delete-right", Action::DeleteRight),
+    ("delete-word", Action::DeleteWord),
+    ("delete-to-start", Action::DeleteToStart),
+    ("delete-to-end", Action::DeleteToEnd),
+    ("up", Action::Up),
+    ("down", Action::Down),
+    ("select-prev", Action::SelectPrev),
+    ("select-next", Action::SelectNext),
+    ("select-first", Action::SelectFirst),
+    ("select-last", Action::SelectLast),
+    ("page-prev", Action::PagePrev),
+    ("page-next", Action::PageNext),
+    ("complete", Action::Complete),
+    ("toggle-mark", Action::ToggleMark),
+    ("accept", Action::Accept),
+    ("accept-input", Action::AcceptInput),
+    ("cancel", Action::Cancel),
+    ("paste-primary", Action::PastePrimary),
+    ("paste-clipboard", Action::PasteClipboard),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTION_NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, action)| action)
+    }
+}
+
+const MODIFIERS_MASK: u32 = xlib::ShiftMask | xlib::ControlMask | xlib::Mod1Mask | xlib::Mod4Mask;
+
+/// A keysym pressed together with a set of modifiers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct KeyChord {
+    modifiers: u32,
+    keysym: u32,
+}
+
+impl KeyChord {
+    pub fn new(modifiers: u32, keysym: u32) -> KeyChord {
+        KeyChord { modifiers: modifiers & MODIFIERS_MASK, keysym: keysym }
+    }
+
+    /// Parses chords such as "Ctrl+Shift+y", "Alt+G" or "Return".
+    ///
+    /// Uppercase letters imply Shift, so "Alt+G" is the same chord as "Alt+Shift+g".
+    pub fn parse(chord: &str) -> Result<KeyChord, String> {
+        let parts: Vec<&str> = chord.split('+').map(|part| part.trim()).collect();
+        let (key, modifier_names) = parts.split_last().unwrap();
+
+        let mut modifiers = 0;
+        for name in modifier_names {
+            modifiers |= match &name.to_lowercase()[..] {
+                "ctrl" | "control" => xlib::ControlMask,
+                "alt" | "mod1" => xlib::Mod1Mask,
+                "shift" => xlib::ShiftMask,
+                "super" | "mod4" => xlib::Mod4Mask,
+                _ => return Err(format!("unknown modifier '{}' in '{}'", name, chord))
+            };
+        }
+
+        let mut keysym = string_to_keysym(key);
+        if keysym == 0 {
+            return Err(format!("unknown key '{}' in '{}'", key, chord));
+        }
+        if (keysym::XK_A..=keysym::XK_Z).contains(&keysym) {
+            keysym += keysym::XK_a - keysym::XK_A;
+            modifiers |= xlib::ShiftMask;
+        }
+        Ok(KeyChord::new(modifiers, keysym))
+    }
+
+    /// Name of the chord in the form `parse` reads, such as "Ctrl+Shift+y".
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+        let modifiers = [(xlib::ControlMask, "Ctrl+"), (xlib::Mod1Mask, "Alt+"), (xlib::ShiftMask, "Shift+"), (xlib::Mod4Mask, "Super+")];
+        for &(mask, prefix) in modifiers.iter() {
+            if self.modifiers & mask != 0 {
+                name.push_str(prefix);
+            }
+        }
+        name.push_str(&keysym_to_string(self.keysym));
+        name
+    }
+}
+
+fn keysym_to_string(keysym: u32) -> String {
+    let name = unsafe { xlib::XKeysymToString(keysym as xlib::KeySym) };
+    if name.is_null() {
+        return format!("0x{:x}", keysym)
+    }
+    unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+}
+
+fn string_to_keysym(name: &str) -> u32 {
+    match CString::new(name) {
+        Ok(name) => unsafe { xlib::XStringToKeysym(name.as_ptr()) as u32 },
+        Err(_) => 0
+    }
+}
+
+/// Table mapping key chords to actions.
+pub struct Bindings {
+    map: HashMap<KeyChord, Action>,
+}
+
+impl Bindings {
+    /// Creates the default Emacs-style bindings.
+    pub fn new() -> Bindings {
+        let mut bindings = Bindings { map: HashMap::new() };
+        let defaults = [
+            ("Left", Action::Left),
+            ("Right", Action::Right),
+            ("Up", Action::Up),
+            ("Down", Action::Down),
+            ("Home", Action::Home),
+            ("End", Action::End),
+            ("Prior", Action::PagePrev),
+            ("Next", Action::PageNext),
+            ("BackSpace", Action::DeleteLeft),
+            ("Delete", Action::DeleteRight),
+            ("Tab", Action::Complete),
+            ("Return", Action::Accept),
+            ("KP_Enter", Action::Accept),
+            ("Shift+Return", Action::AcceptInput),
+            ("Shift+KP_Enter", Action::AcceptInput),
+            ("Ctrl+Return", Action::ToggleMark),
+            ("Escape", Action::Cancel),
+            ("Ctrl+a", Action::Home),
+            ("Ctrl+b", Action::Left),
+            ("Ctrl+c", Action::Cancel),
+            ("Ctrl+d", Action::DeleteRight),
+            ("Ctrl+e", Action::End),
+            ("Ctrl+f", Action::Right),
+            ("Ctrl+h", Action::DeleteLeft),
+            ("Ctrl+i", Action::Complete),
+            ("Ctrl+j", Action::Accept),
+            ("Ctrl+m", Action::Accept),
+            ("Ctrl+Shift+j", Action::AcceptInput),
+            ("Ctrl+Shift+m", Action::AcceptInput),
+            ("Ctrl+k", Action::DeleteToEnd),
+            ("Ctrl+n", Action::SelectNext),
+            ("Ctrl+p", Action::SelectPrev),
+            ("Ctrl+u", Action::DeleteToStart),
+            ("Ctrl+w", Action::DeleteWord),
+            ("Ctrl+y", Action::PastePrimary),
+            ("Ctrl+Shift+y", Action::PasteClipboard),
+            ("Alt+g", Action::Home),
+            ("Alt+G", Action::End),
+            ("Alt+h", Action::SelectPrev),
+            ("Alt+j", Action::PageNext),
+            ("Alt+k", Action::PagePrev),
+            ("Alt+l", Action::SelectNext),
+        ];
+        for &(chord, action) in defaults.iter() {
+            bindings.bind(KeyChord::parse(chord).unwrap(), action);
+        }
+        bindings
+    }
+
+    pub fn bind(&mut self, chord: KeyChord, action: Action) {
+        self.map.insert(chord, action);
+    }
+
+    pub fn unbind(&mut self, chord: KeyChord) {
+        self.map.remove(&chord);
+    }
+
+    /// Applies a "CHORD = ACTION" binding, "CHORD = none" removes the chord.
+    pub fn parse_binding(&mut self, binding: &str) -> Result<(), String> {
+        let mut parts = binding.splitn(2, '=');
+        let chord = parts.next().unwrap().trim();
+        let action_name = match parts.next() {
+            Some(action_name) => action_name.trim(),
+            None => return Err(format!("expected CHORD=ACTION, found '{}'", binding))
+        };
+
+        let chord = KeyChord::parse(chord)?;
+        if action_name == "none" {
+            self.unbind(chord);
+            return Ok(())
+        }
+        match Action::from_name(action_name) {
+            Some(action) => {
+                self.bind(chord, action);
+                Ok(())
+            },
+            None => Err(format!("unknown action '{}'", action_name))
+        }
+    }
+
+    /// Loads "CHORD = ACTION" lines from a file, blank lines and lines
+    /// starting with '#' are ignored.
+    pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("{}: {}", path, e))
+        };
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Err(format!("{}:{}: {}", path, n + 1, e))
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            if let Err(e) = self.parse_binding(line) {
+                return Err(format!("{}:{}: {}", path, n + 1, e))
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the action for a key event, chords without Shift also match
+    /// when Shift is held.
+    pub fn lookup(&self, state: u32, keysym: u32) -> Option<Action> {
+        let chord = KeyChord::new(state, keysym);
+        match self.map.get(&chord) {
+            Some(action) => Some(*action),
+            None => self.map.get(&KeyChord::new(state & !xlib::ShiftMask, keysym)).copied()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Bindings, KeyChord};
+    use x11::xlib;
+    use x11::keysym;
+
+    #[test]
+    fn test_parse_chord() {
+        assert!(KeyChord::parse("Ctrl+Shift+y").unwrap() == KeyChord::new(xlib::ControlMask | xlib::ShiftMask, keysym::XK_y));
+        assert!(KeyChord::parse("Alt+G").unwrap() == KeyChord::new(xlib::Mod1Mask | xlib::ShiftMask, keysym::XK_g));
+        assert!(KeyChord::parse("Return").unwrap() == KeyChord::new(0, keysym::XK_Return));
+        assert!(KeyChord::parse("Hyper+a").is_err());
+        assert!(KeyChord::parse("Ctrl+nokey").is_err());
+    }
+
+    #[test]
+    fn test_chord_names() {
+        for name in ["Ctrl+Shift+y", "Alt+Shift+g", "Return", "Super+KP_Enter"].iter() {
+            assert!(KeyChord::parse(name).unwrap().name() == *name);
+        }
+    }
+
+    #[test]
+    fn test_default_bindings() {
+        let bindings = Bindings::new();
+        assert!(bindings.lookup(xlib::ControlMask, keysym::XK_w) == Some(Action::DeleteWord));
+        assert!(bindings.lookup(xlib::ShiftMask, keysym::XK_Return) == Some(Action::AcceptInput));
+        assert!(bindings.lookup(xlib::ShiftMask, keysym::XK_Left) == Some(Action::Left));
+        assert!(bindings.lookup(0, keysym::XK_a) == None);
+    }
+
+    #[test]
+    fn test_parse_binding_overrides() {
+        let mut bindings = Bindings::new();
+        bindings.parse_binding("Ctrl+j = select-next").unwrap();
+        bindings.parse_binding("Ctrl+w=none").unwrap();
+        assert!(bindings.lookup(xlib::ControlMask, keysym::XK_j) == Some(Action::SelectNext));
+        assert!(bindings.lookup(xlib::ControlMask, keysym::XK_w) == None);
+        assert!(bindings.parse_binding("Ctrl+j = fly").unwrap_err() == "unknown action 'fly'");
+        assert!(bindings.parse_binding("Ctrl+j").is_err());
+    }
+
+    #[test]
+    fn test_action_from_name() {
+        assert!(Action::from_name("page-next") == Some(Action::PageNext));
+        assert!(Action::from_name("page-up") == None);
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
+/// A value of the configuration file.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<String>),
+}
+
+/// A `key = value` line of the configuration file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+/// Base directory for the configuration, `$XDG_CONFIG_HOME/rumenu`.
+pub fn config_dir() -> String {
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(ref dir) if dir.len() > 0 => dir.clone(),
+        _ => format!("{}/.config", env::var("HOME").unwrap_or_default())
+    };
+    format!("{}/rumenu", config_home)
+}
+
+/// Configuration file in a small subset of TOML: `[section]` headers and
+/// `key = value` lines with strings, integers, booleans and arrays of strings.
+///
+/// Profiles live in `[profile.NAME]` sections and key bindings in
+/// `[bindings]` or `[profile.NAME.bindings]` sections.
+pub struct ConfigFile {
+    pub path: String,
+    sections: HashMap<String, Vec<Entry>>,
+    /// Section headers with their line.
+    headers: Vec<(String, usize)>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &str) -> Result<ConfigFile, String> {
+        let mut content = String::new();
+        match File::open(path) {
+            Ok(mut file) => if let Err(e) = file.read_to_string(&mut content) {
+                return Err(format!("{}: {}", path, e))
+            },
+            Err(e) => return Err(format!("{}: {}", path, e))
+        }
+        ConfigFile::parse(path, &content)
+    }
+
+    pub fn parse(path: &str, content: &str) -> Result<ConfigFile, String> {
+        let mut sections = HashMap::new();
+        let mut headers = vec![];
+        let mut section = String::new();
+        sections.insert(section.clone(), vec![]);
+
+        for (n, line) in content.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue
+            }
+            let error = |message: String| format!("{}:{}: {}", path, n + 1, message);
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(error(format!("unterminated section header '{}'", line)))
+                }
+                section = line[1..line.len() - 1].trim().to_string();
+                if section.is_empty() {
+                    return Err(error("empty section name".to_string()))
+                }
+                sections.entry(section.clone()).or_insert(vec![]);
+                headers.push((section.clone(), n + 1));
+                continue
+            }
+
+            let (key, rest) = parse_key(line).map_err(&error)?;
+            let rest = rest.trim_start();
+            if !rest.starts_with('=') {
+                return Err(error(format!("expected '=' after '{}'", key)))
+            }
+            let value = parse_value(rest[1..].trim()).map_err(&error)?;
+            sections.get_mut(&section).unwrap().push(Entry { key: key, value: value, line: n + 1 });
+        }
+        Ok(ConfigFile { path: path.to_string(), sections: sections, headers: headers })
+    }
+
+    /// Checks that every section is a known one and that the settings of the
+    /// top level and of the profiles are among `keys`, so typos do not go
+    /// unnoticed. The keys of the bindings are checked when they are read.
+    pub fn check(&self, keys: &[&str]) -> Result<(), String> {
+        let mut errors: Vec<(usize, String)> = vec![];
+        for &(ref section, line) in &self.headers {
+            let known = section == "bindings" || section.starts_with("profile.") && section.len() > "profile.".len();
+            if !known {
+                errors.push((line, format!("unknown section '{}'", section)));
+            }
+        }
+        for (section, entries) in &self.sections {
+            if section == "bindings" || section.ends_with(".bindings") {
+                continue
+            }
+            for entry in entries.iter().filter(|entry| !keys.contains(&&entry.key[..])) {
+                errors.push((entry.line, format!("unknown key '{}'", entry.key)));
+            }
+        }
+        // The first one in the file
+        match errors.into_iter().min_by_key(|&(line, _)| line) {
+            Some((line, message)) => Err(format!("{}:{}: {}", self.path, line, message)),
+            None => Ok(())
+        }
+    }
+
+    pub fn section(&self, name: &str) -> &[Entry] {
+        match self.sections.get(name) {
+            Some(entries) => entries,
+            None => &[]
+        }
+    }
+
+    pub fn has_profile(&self, profile: &str) -> bool {
+        self.sections.contains_key(&format!("profile.{}", profile))
+    }
+
+    /// Settings of the top level section, overridden by the ones of the profile.
+    pub fn settings(&self, profile: Option<&str>) -> Vec<&Entry> {
+        let mut entries: Vec<&Entry> = self.section("").iter().collect();
+        if let Some(profile) = profile {
+            entries.extend(self.section(&format!("profile.{}", profile)));
+        }
+        entries
+    }
+
+    /// Key bindings of the `[bindings]` section, overridden by the ones of the profile.
+    pub fn bindings(&self, profile: Option<&str>) -> Vec<&Entry> {
+        let mut entries: Vec<&Entry> = self.section("bindings").iter().collect();
+        if let Some(profile) = profile {
+            entries.extend(self.section(&format!("profile.{}.bindings", profile)));
+        }
+        entries
+    }
+
+    /// Location of an entry for error messages.
+    pub fn origin(&self, entry: &Entry) -> String {
+        format!("{}:{}", self.path, entry.line)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (position, c) in line.char_indices() {
+        match c {
+            '\\' if in_string && !escaped => { escaped = true; continue },
+            '"' if !escaped => in_string = !in_string,
+            '#' if !in_string => return &line[..position],
+            _ => ()
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_key(line: &str) -> Result<(String, &str), String> {
+    if line.starts_with('"') {
+        let (key, length) = parse_string(line)?;
+        Ok((key, &line[length..]))
+    } else {
+        let length = line.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-')).unwrap_or(line.len());
+        if length == 0 {
+            return Err(format!("expected a key, found '{}'", line))
+        }
+        Ok((line[..length].to_string(), &line[length..]))
+    }
+}
+
+/// Parses a double quoted string at the start of `text`, returning it and
+/// the number of bytes consumed.
+fn parse_string(text: &str) -> Result<(String, usize), String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((position, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, position + 1)),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, c)) => return Err(format!("unknown escape sequence '\\{}'", c)),
+                None => break
+            },
+            c => value.push(c)
+        }
+    }
+    Err(format!("unterminated string {}", text))
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if text.starts_with('"') {
+        let (value, length) = parse_string(text)?;
+        if text[length..].trim().len() > 0 {
+            return Err(format!("unexpected '{}' after value", text[length..].trim()))
+        }
+        Ok(Value::String(value))
+    } else if let Some(rest) = text.strip_prefix('[') {
+        let mut values = vec![];
+        let mut rest = rest.trim_start();
+        loop {
+            if rest.starts_with(']') {
+                rest = &rest[1..];
+                break
+            }
+            if !rest.starts_with('"') {
+                return Err(format!("expected a string in array, found '{}'", rest))
+            }
+            let (value, length) = parse_string(rest)?;
+            values.push(value);
+            rest = rest[length..].trim_start();
+            if rest.starts_with(',') {
+                rest = rest[1..].trim_start();
+            } else if !rest.starts_with(']') {
+                return Err(format!("expected ',' or ']' in array, found '{}'", rest))
+            }
+        }
+        if rest.trim().len() > 0 {
+            return Err(format!("unexpected '{}' after value", rest.trim()))
+        }
+        Ok(Value::Array(values))
+    } else if text == "true" || text == "false" {
+        Ok(Value::Boolean(text == "true"))
+    } else {
+        match text.parse::<i64>() {
+            Ok(value) => Ok(Value::Integer(value)),
+            Err(_) => Err(format!("invalid value '{}'", text))
+        }
+    }
+}
+
+/// Settings collected from several sources, a value set later overrides
+/// the previous one.
+pub struct Layers {
+    values: HashMap<String, (Value, String)>,
+}
+
+impl Layers {
+    pub fn new() -> Layers {
+        Layers { values: HashMap::new() }
+    }
+
+    /// Sets `key`, `origin` describes where the value comes from for error messages.
+    pub fn set(&mut self, key: &str, value: Value, origin: String) {
+        self.values.insert(key.to_string(), (value, origin));
+    }
+
+    /// Where the value of `key` comes from.
+    pub fn origin(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|&(_, ref origin)| &origin[..])
+    }
+
+    pub fn get_string(&self, key: &str) -> Result<Option<String>, String> {
+        match self.values.get(key) {
+            Some(&(Value::String(ref value), _)) => Ok(Some(value.clone())),
+            Some(&(_, ref origin)) => Err(format!("{}: expected a string for '{}'", origin, key)),
+            None => Ok(None)
+        }
+    }
+
+    /// Gets an array of strings, a single string is taken as a one element array.
+    pub fn get_strings(&self, key: &str) -> Result<Option<Vec<String>>, String> {
+        match self.values.get(key) {
+            Some(&(Value::Array(ref values), _)) => Ok(Some(values.clone())),
+            Some(&(Value::String(ref value), _)) => Ok(Some(vec![value.clone()])),
+            Some(&(_, ref origin)) => Err(format!("{}: expected a list of strings for '{}'", origin, key)),
+            None => Ok(None)
+        }
+    }
+
+    /// Gets an integer, strings coming from the command line or the
+    /// environment are parsed.
+    pub fn get_integer(&self, key: &str) -> Result<Option<i64>, String> {
+        match self.values.get(key) {
+            Some(&(Value::Integer(value), _)) => Ok(Some(value)),
+            Some(&(Value::String(ref value), ref origin)) => match value.trim().parse::<i64>() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(format!("{}: expected an integer for '{}', found '{}'", origin, key, value))
+            },
+            Some(&(_, ref origin)) => Err(format!("{}: expected an integer for '{}'", origin, key)),
+            None => Ok(None)
+        }
+    }
+
+    /// Gets a boolean, "true" and "false" strings are accepted too.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.values.get(key) {
+            Some(&(Value::Boolean(value), _)) => Ok(Some(value)),
+            Some(&(Value::String(ref value), _)) if value == "true" || value == "false" => Ok(Some(value == "true")),
+            Some(&(_, ref origin)) => Err(format!("{}: expected true or false for '{}'", origin, key)),
+            None => Ok(None)
+        }
+    }
+
+    /// Gets one of the `choices`, the value must be a string.
+    pub fn get_choice(&self, key: &str, choices: &[&str]) -> Result<Option<String>, String> {
+        match self.get_string(key)? {
+            Some(ref value) if !choices.contains(&&value[..]) => {
+                let origin = &self.values[key].1;
+                Err(format!("{}: invalid value '{}' for '{}', expected one of: {}", origin, value, key, choices.join(", ")))
+            },
+            value => Ok(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigFile, Layers, Value};
+
+    const CONFIG: &'static str = r#"
+# rumenu configuration
+font = ["DejaVu Sans Mono:size=11", "Noto Sans CJK JP:size=11"]
+lines = 0
+prompt = "run # "  # comment after a string with a hash
+
+[bindings]
+"Ctrl+j" = "select-next"
+
+[profile.files]
+lines = 15
+matcher = "fuzzy"
+
+[profile.files.bindings]
+"Ctrl+k" = "select-prev"
+"#;
+
+    #[test]
+    fn test_parse_values() {
+        let config = ConfigFile::parse("config.toml", CONFIG).unwrap();
+        let entries = config.section("");
+        assert!(entries.len() == 3);
+        assert!(entries[0].value == Value::Array(vec!["DejaVu Sans Mono:size=11".to_string(), "Noto Sans CJK JP:size=11".to_string()]));
+        assert!(entries[1].value == Value::Integer(0));
+        assert!(entries[2].value == Value::String("run # ".to_string()));
+        assert!(entries[2].line == 5);
+    }
+
+    #[test]
+    fn test_profile_overrides_top_level() {
+        let config = ConfigFile::parse("config.toml", CONFIG).unwrap();
+        assert!(config.has_profile("files"));
+        assert!(!config.has_profile("nope"));
+
+        let mut layers = Layers::new();
+        for entry in config.settings(Some("files")) {
+            layers.set(&entry.key, entry.value.clone(), config.origin(entry));
+        }
+        assert!(layers.get_integer("lines").unwrap() == Some(15));
+        assert!(layers.get_string("matcher").unwrap() == Some("fuzzy".to_string()));
+        assert!(layers.get_string("prompt").unwrap() == Some("run # ".to_string()));
+
+        let bindings: Vec<&str> = config.bindings(Some("files")).iter().map(|e| &e.key[..]).collect();
+        assert!(bindings == vec!["Ctrl+j", "Ctrl+k"]);
+    }
+
+    #[test]
+    fn test_parse_errors_name_file_and_line() {
+        let error = ConfigFile::parse("/tmp/config.toml", "lines = 3\nprompt = \"open\n").err().unwrap();
+        assert!(error == "/tmp/config.toml:2: unterminated string \"open");
+        let error = ConfigFile::parse("/tmp/config.toml", "\n\nlines 3\n").err().unwrap();
+        assert!(error == "/tmp/config.toml:3: expected '=' after 'lines'");
+    }
+
+    #[test]
+    fn test_unknown_keys_and_sections() {
+        let keys = ["font", "lines", "prompt", "matcher"];
+        assert!(ConfigFile::parse("config.toml", CONFIG).unwrap().check(&keys).is_ok());
+        let config = ConfigFile::parse("config.toml", "lines = 3\nlnes = 10\n").unwrap();
+        assert!(config.check(&keys).err().unwrap() == "config.toml:2: unknown key 'lnes'");
+        let config = ConfigFile::parse("config.toml", "[profile.files]\nline = 10\n").unwrap();
+        assert!(config.check(&keys).err().unwrap() == "config.toml:2: unknown key 'line'");
+        let config = ConfigFile::parse("config.toml", "lines = 3\n\n[binding]\n\"Tab\" = \"accept\"\n").unwrap();
+        assert!(config.check(&keys).err().unwrap() == "config.toml:3: unknown section 'binding'");
+        let config = ConfigFile::parse("config.toml", "[profiles.files]\n").unwrap();
+        assert!(config.check(&keys).err().unwrap() == "config.toml:1: unknown section 'profiles.files'");
+        let config = ConfigFile::parse("config.toml", "[x]\n[profile.a]\nlnes = 1\n[bindings]\nlnes = 1\n").unwrap();
+        assert!(config.check(&keys).err().unwrap() == "config.toml:1: unknown section 'x'");
+    }
+
+    #[test]
+    fn test_layers_report_origin() {
+        let mut layers = Layers::new();
+        layers.set("lines", Value::Boolean(true), "config.toml:7".to_string());
+        assert!(layers.get_integer("lines").err().unwrap() == "config.toml:7: expected an integer for 'lines'");
+
+        layers.set("lines", Value::String("ten".to_string()), "RUMENU_LINES".to_string());
+        assert!(layers.get_integer("lines").err().unwrap() == "RUMENU_LINES: expected an integer for 'lines', found 'ten'");
+
+        layers.set("multi", Value::String("yes".to_string()), "RUMENU_MULTI".to_string());
+        assert!(layers.get_bool("multi").err().unwrap() == "RUMENU_MULTI: expected true or false for 'multi'");
+        layers.set("multi", Value::String("true".to_string()), "RUMENU_MULTI".to_string());
+        assert!(layers.get_bool("multi").unwrap() == Some(true));
+
+        layers.set("case", Value::String("upper".to_string()), "--case".to_string());
+        assert!(layers.get_choice("case", &["sensitive", "insensitive"]).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::io;
+
+/// Errors that end rumenu, each one with its own exit status:
+///
+/// | status | meaning                                   |
+/// |--------|-------------------------------------------|
+/// | 0      | an item was accepted                      |
+/// | 1      | the user cancelled the menu               |
+/// | 2      | bad arguments or configuration            |
+/// | 3      | the X display cannot be opened            |
+/// | 4      | the keyboard or the pointer cannot be     |
+/// |        | grabbed, or the focus taken when embedded |
+/// | 5      | the items cannot be read                  |
+/// | 10     | the input text was accepted, not an item  |
+#[derive(Debug)]
+pub enum RumenuError {
+    Cancelled,
+    BadArguments(String),
+    NoDisplay(String),
+    GrabFailed,
+    PointerGrabFailed,
+    FocusFailed,
+    Io(String, io::Error),
+}
+
+impl RumenuError {
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            RumenuError::Cancelled => 1,
+            RumenuError::BadArguments(_) => 2,
+            RumenuError::NoDisplay(_) => 3,
+            // Either way the menu cannot get the keys
+            RumenuError::GrabFailed | RumenuError::PointerGrabFailed | RumenuError::FocusFailed => 4,
+            RumenuError::Io(_, _) => 5,
+        }
+    }
+}
+
+impl fmt::Display for RumenuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RumenuError::Cancelled => write!(f, "cancelled"),
+            RumenuError::BadArguments(ref message) => write!(f, "{}", message),
+            RumenuError::NoDisplay(ref display) => write!(f, "cannot open display '{}'", display),
+            RumenuError::GrabFailed => write!(f, "cannot grab keyboard"),
+            RumenuError::PointerGrabFailed => write!(f, "cannot grab pointer"),
+            RumenuError::FocusFailed => write!(f, "cannot take the input focus from the embedding window"),
+            RumenuError::Io(ref path, ref e) => write!(f, "cannot read {}: {}", path, e),
+        }
+    }
+}
+
+impl From<String> for RumenuError {
+    fn from(message: String) -> RumenuError {
+        RumenuError::BadArguments(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::RumenuError;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = vec![
+            RumenuError::Cancelled,
+            RumenuError::BadArguments("bad".to_string()),
+            RumenuError::NoDisplay(":0".to_string()),
+            RumenuError::GrabFailed,
+            RumenuError::Io("items".to_string(), io::Error::new(io::ErrorKind::NotFound, "gone")),
+        ];
+        let codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        assert!(codes == vec![1, 2, 3, 4, 5]);
+        assert!(RumenuError::FocusFailed.exit_code() == RumenuError::GrabFailed.exit_code());
+        assert!(RumenuError::PointerGrabFailed.exit_code() == RumenuError::GrabFailed.exit_code());
+    }
+
+    #[test]
+    fn test_messages() {
+        assert!(RumenuError::NoDisplay(":1".to_string()).to_string() == "cannot open display ':1'");
+        let error = RumenuError::Io("/tmp/cache".to_string(), io::Error::new(io::ErrorKind::NotFound, "No such file"));
+        assert!(error.to_string() == "cannot read /tmp/cache: No such file");
+        assert!(RumenuError::FocusFailed.to_string() != RumenuError::GrabFailed.to_string());
+    }
+}
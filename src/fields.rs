@@ -0,0 +1,131 @@
+/// Fields `start..end` of a line, counted from 1, negative ones count
+/// from the last field and a missing bound is the first or last field.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FieldRange {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl FieldRange {
+    fn parse_bound(bound: &str) -> Result<Option<i64>, ()> {
+        if bound.is_empty() {
+            return Ok(None)
+        }
+        match bound.parse::<i64>() {
+            Ok(0) | Err(_) => Err(()),
+            Ok(n) => Ok(Some(n))
+        }
+    }
+
+    /// Zero based index of the field `n` among `count` fields.
+    fn resolve(n: i64, count: usize) -> i64 {
+        if n > 0 { n - 1 } else { count as i64 + n }
+    }
+
+    /// Indices of the fields in the range, among `count` fields.
+    fn indices(&self, count: usize) -> ::std::ops::Range<usize> {
+        let start = FieldRange::resolve(self.start.unwrap_or(1), count).max(0);
+        let end = FieldRange::resolve(self.end.unwrap_or(-1), count).min(count as i64 - 1);
+        if start > end { 0..0 } else { start as usize..end as usize + 1 }
+    }
+}
+
+/// Parses field lists like "2", "1,3", "2..", "..-2" or "-1".
+pub fn parse_field_list(list: &str) -> Result<Vec<FieldRange>, String> {
+    let error = || format!("invalid field list '{}'", list);
+    let mut ranges = vec![];
+    for part in list.split(',') {
+        let part = part.trim();
+        let range = match part.find("..") {
+            Some(n) => FieldRange {
+                start: FieldRange::parse_bound(&part[..n]).map_err(|_| error())?,
+                end: FieldRange::parse_bound(&part[n + 2..]).map_err(|_| error())?,
+            },
+            None => {
+                let n = FieldRange::parse_bound(part).map_err(|_| error())?;
+                if n.is_none() {
+                    return Err(error())
+                }
+                FieldRange { start: n, end: n }
+            }
+        };
+        ranges.push(range);
+    }
+    Ok(ranges)
+}
+
+/// Turns the escapes "\t" and "\\" of a delimiter given on the command line
+/// into the characters.
+pub fn unescape_delimiter(delimiter: &str) -> String {
+    delimiter.replace("\\\\", "\u{0}").replace("\\t", "\t").replace("\u{0}", "\\")
+}
+
+/// How to split the lines into the text shown and matched and the text
+/// printed when they are accepted.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Fields {
+    delimiter: String,
+    with_nth: Option<Vec<FieldRange>>,
+    accept_nth: Option<Vec<FieldRange>>,
+}
+
+impl Fields {
+    pub fn new(delimiter: String, with_nth: Option<Vec<FieldRange>>, accept_nth: Option<Vec<FieldRange>>) -> Fields {
+        Fields { delimiter: delimiter, with_nth: with_nth, accept_nth: accept_nth }
+    }
+
+    /// The fields of `ranges` joined with the delimiter, the whole line without ranges.
+    fn select(&self, line: &str, fields: &[&str], ranges: &Option<Vec<FieldRange>>) -> String {
+        match *ranges {
+            Some(ref ranges) => {
+                let selected: Vec<&str> = ranges.iter().flat_map(|range| fields[range.indices(fields.len())].iter().cloned()).collect();
+                selected.join(&self.delimiter)
+            },
+            None => line.to_string()
+        }
+    }
+
+    /// Splits `line` into the text to show and the text to print.
+    pub fn split(&self, line: &str) -> (String, String) {
+        let fields: Vec<&str> = line.split(&self.delimiter[..]).collect();
+        (self.select(line, &fields, &self.with_nth), self.select(line, &fields, &self.accept_nth))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_field_list, unescape_delimiter, Fields};
+
+    fn split(with_nth: &str, accept_nth: &str, line: &str) -> (String, String) {
+        let fields = Fields::new("\t".to_string(), Some(parse_field_list(with_nth).unwrap()), Some(parse_field_list(accept_nth).unwrap()));
+        fields.split(line)
+    }
+
+    #[test]
+    fn test_parse_field_list() {
+        assert!(parse_field_list("1,3..,..-2,-1").unwrap().len() == 4);
+        assert!(parse_field_list("..").unwrap().len() == 1);
+        for list in ["", "0", "a", "1,", "1...2"].iter() {
+            assert!(parse_field_list(list).err().unwrap() == format!("invalid field list '{}'", list));
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let line = "firefox\tFirefox — web browser\tinternet";
+        assert!(split("2", "1", line) == ("Firefox — web browser".to_string(), "firefox".to_string()));
+        assert!(split("2..", "-1", line) == ("Firefox — web browser\tinternet".to_string(), "internet".to_string()));
+        assert!(split("3,1", "..-2", line) == ("internet\tfirefox".to_string(), "firefox\tFirefox — web browser".to_string()));
+        // Missing fields are left out
+        assert!(split("4", "2..5", "one\ttwo") == ("".to_string(), "two".to_string()));
+        let fields = Fields::new(" ".to_string(), Some(parse_field_list("2").unwrap()), None);
+        assert!(fields.split("vim text editor") == ("text".to_string(), "vim text editor".to_string()));
+    }
+
+    #[test]
+    fn test_unescape_delimiter() {
+        assert!(unescape_delimiter("\\t") == "\t");
+        assert!(unescape_delimiter("\\\\t") == "\\t");
+        assert!(unescape_delimiter(":") == ":");
+    }
+}
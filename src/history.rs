@@ -0,0 +1,260 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libc::{flock, LOCK_EX};
+use error::RumenuError;
+use matches::best_first;
+
+/// Entries kept in the history file, the least frecent ones are dropped.
+const HISTORY_SIZE: usize = 1000;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// Seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// An accepted line, how many times it was accepted and when it was last.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entry {
+    pub line: String,
+    pub count: u32,
+    pub last: u64,
+}
+
+impl Entry {
+    /// Frequency weighted by recency: uses in the last hour count four
+    /// times as much as uses in the last week.
+    pub fn frecency(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last);
+        let weight = if age < HOUR {
+            4.0
+        } else if age < DAY {
+            2.0
+        } else if age < WEEK {
+            1.0
+        } else {
+            0.25
+        };
+        self.count as f64 * weight
+    }
+}
+
+/// Escapes the line breaks and backslashes of a line, so that it takes a
+/// single line of the history file.
+fn escape(line: &str) -> String {
+    line.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+/// Reverses `escape`, a backslash before any other character is kept.
+fn unescape(line: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some(c) => { unescaped.push('\\'); unescaped.push(c); },
+            None => unescaped.push('\\')
+        }
+    }
+    unescaped
+}
+
+/// History of the accepted lines, stored one `COUNT<TAB>LAST<TAB>LINE` per
+/// line with the line breaks of `LINE` escaped.
+#[derive(Clone, PartialEq, Debug)]
+pub struct History {
+    entries: Vec<Entry>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History { entries: vec![] }
+    }
+
+    /// Reads the history file, a missing file is an empty history.
+    pub fn load(path: &str) -> Result<History, RumenuError> {
+        History::read(path).map_err(|e| RumenuError::Io(path.to_string(), e))
+    }
+
+    fn read(path: &str) -> io::Result<History> {
+        let mut content = String::new();
+        match File::open(path) {
+            Ok(mut file) => { file.read_to_string(&mut content)?; },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e)
+        }
+        Ok(History::parse(&content))
+    }
+
+    /// Parses the history, malformed lines are skipped.
+    pub fn parse(content: &str) -> History {
+        let mut history = History::new();
+        for line in content.lines() {
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            if fields.len() < 3 || fields[2].is_empty() {
+                continue
+            }
+            if let (Ok(count), Ok(last)) = (fields[0].parse(), fields[1].parse()) {
+                history.entries.push(Entry { line: unescape(fields[2]), count: count, last: last });
+            }
+        }
+        history
+    }
+
+    fn format(&self) -> String {
+        self.entries.iter().map(|entry| format!("{}\t{}\t{}\n", entry.count, entry.last, escape(&entry.line))).collect()
+    }
+
+    /// Frecency of each line.
+    pub fn frecency(&self, now: u64) -> HashMap<String, f64> {
+        self.entries.iter().map(|entry| (entry.line.clone(), entry.frecency(now))).collect()
+    }
+
+    /// The lines, most recently accepted first.
+    pub fn recent(&self) -> Vec<String> {
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| Reverse(entry.last));
+        entries.into_iter().map(|entry| entry.line.clone()).collect()
+    }
+
+    /// Counts one more use of `line` at `now`.
+    pub fn add(&mut self, line: &str, now: u64) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.line == line) {
+            entry.count += 1;
+            entry.last = now;
+            return
+        }
+        self.entries.push(Entry { line: line.to_string(), count: 1, last: now });
+    }
+
+    /// Keeps the `size` most frecent entries.
+    pub fn prune(&mut self, size: usize, now: u64) {
+        if self.entries.len() <= size {
+            return
+        }
+        self.entries.sort_by(|x, y| best_first(x.frecency(now), y.frecency(now)).then(y.last.cmp(&x.last)));
+        self.entries.truncate(size);
+    }
+
+    /// Adds the accepted `lines` to the history file.
+    ///
+    /// Writers take turns on a lock file and each one re-reads the history
+    /// under the lock, so several menus closing at once lose no entries.
+    /// The new history replaces the old one with a rename, so readers see
+    /// either of them complete.
+    pub fn record(path: &str, lines: &[String]) -> io::Result<()> {
+        let lock = OpenOptions::new().write(true).create(true).open(format!("{}.lock", path))?;
+        if unsafe { flock(lock.as_raw_fd(), LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        let now = now();
+        let mut history = History::read(path)?;
+        for line in lines {
+            history.add(line, now);
+        }
+        history.prune(HISTORY_SIZE, now);
+
+        let temp_path = format!("{}.{}", path, process::id());
+        File::create(&temp_path)
+            .and_then(|mut file| file.write_all(history.format().as_bytes()).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&temp_path, path))
+        // The lock is released when the file is closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::thread;
+    use super::{History, DAY, HOUR, WEEK};
+
+    #[test]
+    fn test_parse_and_format() {
+        let history = History::parse("3\t100\tfirefox\nbroken line\n1\t200\tgimp\tfile.png\n");
+        assert!(history.recent() == vec!["gimp\tfile.png".to_string(), "firefox".to_string()]);
+        assert!(History::parse(&history.format()) == history);
+    }
+
+    #[test]
+    fn test_line_breaks_are_escaped() {
+        let mut history = History::new();
+        history.add("two\nlines", 100);
+        history.add("C:\\new\\x\r", 100);
+        history.add("tab\tseparated", 100);
+        assert!(history.format().lines().count() == 3);
+        assert!(History::parse(&history.format()) == history);
+        assert!(History::parse("1\t100\tkeeps \\x\n").recent() == vec!["keeps \\x".to_string()]);
+    }
+
+    #[test]
+    fn test_frecency_weights_recent_uses() {
+        let now = 10 * WEEK;
+        let mut history = History::new();
+        for _ in 0..3 {
+            history.add("old", now - 2 * WEEK);
+        }
+        history.add("recent", now - HOUR / 2);
+        history.add("today", now - 2 * HOUR);
+        history.add("today", now - 2 * HOUR);
+        let frecency = history.frecency(now);
+        assert!(frecency["old"] == 0.75);
+        assert!(frecency["recent"] == 4.0);
+        assert!(frecency["today"] == 4.0);
+        assert!(frecency["today"] > frecency["old"]);
+        assert!(history.recent() == vec!["recent".to_string(), "today".to_string(), "old".to_string()]);
+    }
+
+    #[test]
+    fn test_prune_keeps_the_most_frecent() {
+        let now = 10 * WEEK;
+        let mut history = History::new();
+        history.add("rare", now - DAY * 2);
+        history.add("often", now - DAY * 2);
+        history.add("often", now - DAY * 2);
+        history.add("new", now);
+        history.prune(2, now);
+        let mut lines = history.recent();
+        lines.sort();
+        assert!(lines == vec!["new".to_string(), "often".to_string()]);
+    }
+
+    #[test]
+    fn test_concurrent_records_keep_every_entry() {
+        let path = env::temp_dir().join(format!("rumenu-history-{}", ::std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+
+        let writers: Vec<_> = (0..8).map(|n| {
+            let path = path.clone();
+            thread::spawn(move || History::record(&path, &[format!("item-{}", n), "shared".to_string()]).unwrap())
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let history = History::load(&path).unwrap();
+        assert!(history.recent().len() == 9);
+        assert!(history.entries.iter().find(|entry| entry.line == "shared").unwrap().count == 8);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(format!("{}.lock", path));
+    }
+}
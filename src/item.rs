@@ -0,0 +1,115 @@
+use fields::Fields;
+use json::Json;
+
+/// How the input lines become items.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Format {
+    /// An item per line, split into fields when there are any.
+    Lines(Option<Fields>),
+    /// A JSON object per line.
+    Jsonl,
+}
+
+/// An item as read from the input.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Item {
+    /// Text shown and matched.
+    pub label: String,
+    /// Text printed on accept, the label when there is none.
+    pub value: Option<String>,
+    /// Text matched but not shown.
+    pub keywords: Option<String>,
+    /// Whether the item can be accepted.
+    pub selectable: bool,
+    /// The JSON object the item was read from.
+    pub object: Option<Json>,
+}
+
+impl Item {
+    pub fn new(label: String) -> Item {
+        Item { label: label, value: None, keywords: None, selectable: true, object: None }
+    }
+
+    /// Reads an item from an input line.
+    pub fn parse(line: String, format: &Format) -> Result<Item, String> {
+        match *format {
+            Format::Lines(None) => Ok(Item::new(line)),
+            Format::Lines(Some(ref fields)) => {
+                let (label, value) = fields.split(&line);
+                Ok(Item { value: Some(value), ..Item::new(label) })
+            },
+            Format::Jsonl => Item::from_json(Json::parse(&line)?),
+        }
+    }
+
+    /// Reads an object with a `label`, and optionally a `value`, `meta`
+    /// keywords and a `selectable` flag. Other members, like `icon` or
+    /// `group`, are only given back on accept.
+    fn from_json(object: Json) -> Result<Item, String> {
+        let label = match object.get("label") {
+            Some(&Json::String(ref label)) => label.clone(),
+            Some(_) => return Err("'label' must be a string".to_string()),
+            None => match object {
+                Json::Object(_) => return Err("missing 'label'".to_string()),
+                _ => return Err("expected an object".to_string())
+            }
+        };
+        let value = match object.get("value") {
+            Some(&Json::String(ref value)) => Some(value.clone()),
+            Some(&Json::Null) | None => None,
+            Some(value) => Some(value.to_string())
+        };
+        let keywords = match object.get("meta") {
+            Some(&Json::String(ref meta)) => Some(meta.clone()),
+            Some(&Json::Array(ref words)) => {
+                let words: Vec<&str> = words.iter().filter_map(|word| word.as_str()).collect();
+                Some(words.join(" "))
+            },
+            Some(&Json::Null) | None => None,
+            Some(_) => return Err("'meta' must be a string or an array of strings".to_string())
+        };
+        let selectable = match object.get("selectable") {
+            Some(&Json::Bool(selectable)) => selectable,
+            None => true,
+            Some(_) => return Err("'selectable' must be true or false".to_string())
+        };
+        Ok(Item { label: label, value: value, keywords: keywords, selectable: selectable, object: Some(object) })
+    }
+}
+
+impl From<String> for Item {
+    fn from(label: String) -> Item {
+        Item::new(label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::Json;
+    use super::{Format, Item};
+
+    fn parse(line: &str) -> Result<Item, String> {
+        Item::parse(line.to_string(), &Format::Jsonl)
+    }
+
+    #[test]
+    fn test_jsonl_items() {
+        let line = r#"{"label": "Firefox", "value": "firefox", "icon": "firefox", "group": "web", "meta": ["browser", "www"]}"#;
+        let item = parse(line).unwrap();
+        assert!(item.label == "Firefox" && item.value == Some("firefox".to_string()));
+        assert!(item.keywords == Some("browser www".to_string()) && item.selectable);
+        assert!(item.object.unwrap().get("group") == Some(&Json::String("web".to_string())));
+
+        let item = parse(r#"{"label": "Apps", "selectable": false, "value": 7}"#).unwrap();
+        assert!(!item.selectable && item.value == Some("7".to_string()) && item.keywords.is_none());
+    }
+
+    #[test]
+    fn test_jsonl_errors() {
+        assert!(parse(r#"{"value": "x"}"#).err().unwrap() == "missing 'label'");
+        assert!(parse(r#"["x"]"#).err().unwrap() == "expected an object");
+        assert!(parse(r#"{"label": 1}"#).err().unwrap() == "'label' must be a string");
+        assert!(parse(r#"{"label": "x", "selectable": "no"}"#).err().unwrap() == "'selectable' must be true or false");
+        assert!(parse(r#"{"label": "x""#).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+
+/// A JSON value, objects keep their keys in order and numbers keep the
+/// text they were written with.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a document holding a single value.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text: text, position: 0 };
+        let value = parser.value()?;
+        parser.skip_spaces();
+        if parser.position < text.len() {
+            return Err(parser.error("unexpected text after the value"))
+        }
+        Ok(value)
+    }
+
+    /// Value of `key` when this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref text) => Some(text),
+            _ => None
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(ref number) => write!(f, "{}", number),
+            Json::String(ref text) => write_string(f, text),
+            Json::Array(ref values) => {
+                write!(f, "[")?;
+                for (n, value) in values.iter().enumerate() {
+                    if n > 0 { write!(f, ",")?; }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(ref members) => {
+                write!(f, "{{")?;
+                for (n, &(ref key, ref value)) in members.iter().enumerate() {
+                    if n > 0 { write!(f, ",")?; }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    /// Byte offset of the next character.
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("column {}: {}", self.text[..self.position].chars().count() + 1, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(c) = self.peek() {
+            if c != ' ' && c != '\t' && c != '\n' && c != '\r' {
+                break
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `c` after the spaces before it.
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_spaces();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c)))
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.text[self.position..].starts_with(word) {
+            return Err(self.error("expected a value"))
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of the text")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut members = vec![];
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(members))
+        }
+        loop {
+            self.skip_spaces();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a key"))
+            }
+            let key = self.string()?;
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members))
+                },
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut values = vec![];
+        self.skip_spaces();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(values))
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values))
+                },
+                _ => return Err(self.error("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        let length = self.text[start..].find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c))).unwrap_or(self.text.len() - start);
+        let number = &self.text[start..start + length];
+        if number.parse::<f64>().is_err() {
+            return Err(self.error(&format!("invalid number '{}'", number)))
+        }
+        self.position += length;
+        Ok(Json::Number(number.to_string()))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.position..self.position + 4).unwrap_or("");
+        match u32::from_str_radix(digits, 16) {
+            Ok(code) if digits.len() == 4 => {
+                self.position += 4;
+                Ok(code)
+            },
+            _ => Err(self.error("invalid \\u escape"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut text = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string"))
+            };
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(text),
+                '\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.position += escape.len_utf8();
+                    match escape {
+                        '"' | '\\' | '/' => text.push(escape),
+                        'b' => text.push('\u{8}'),
+                        'f' => text.push('\u{c}'),
+                        'n' => text.push('\n'),
+                        'r' => text.push('\r'),
+                        't' => text.push('\t'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // Characters outside the BMP come as a surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.text[self.position..].starts_with("\\u") {
+                                self.position += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            text.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                        },
+                        _ => return Err(self.error(&format!("invalid escape '\\{}'", escape)))
+                    }
+                },
+                c if (c as u32) < 0x20 => return Err(self.error("control character in a string")),
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    #[test]
+    fn test_parse() {
+        let value = Json::parse(r#" {"label": "Fire\"foxé😀", "n": -1.5e3, "tags": [true, null, {}], "e": []} "#).unwrap();
+        assert!(value.get("label").and_then(|label| label.as_str()) == Some("Fire\"foxé😀"));
+        assert!(value.get("n") == Some(&Json::Number("-1.5e3".to_string())));
+        assert!(value.get("tags") == Some(&Json::Array(vec![Json::Bool(true), Json::Null, Json::Object(vec![])])));
+        assert!(value.get("missing").is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Json::parse("{\"a\" 1}").err().unwrap() == "column 6: expected ':'");
+        assert!(Json::parse("[1, 2").err().unwrap() == "column 6: expected ',' or ']'");
+        assert!(Json::parse("\"abc").err().unwrap() == "column 5: unterminated string");
+        assert!(Json::parse("{} x").err().unwrap() == "column 4: unexpected text after the value");
+        assert!(Json::parse("-").is_err());
+        assert!(Json::parse("").is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let text = r#"{"label":"a \"b\"\n\u0001","n":10,"x":[true,false,null]}"#;
+        assert!(Json::parse(text).unwrap().to_string() == text);
+    }
+}
@@ -1,109 +1,389 @@
+// The code keeps the idioms it was written with: `Foo { x: x }`, `len() == 0`,
+// `== null_mut()`, `&String` in the matcher API, `writeln!(io::stderr(), ..)`
+// and tests that compare with `==`
+#![allow(clippy::redundant_field_names, clippy::len_zero, clippy::cmp_null, clippy::ptr_arg,
+         clippy::explicit_write, clippy::redundant_static_lifetimes, clippy::needless_borrowed_reference,
+         clippy::partialeq_to_none, clippy::comparison_to_empty, clippy::useless_vec, clippy::cmp_owned)]
+
 extern crate libc;
 extern crate x11;
 extern crate getopts;
+extern crate unicode_segmentation;
 
+mod bindings;
+mod config;
+mod error;
+mod fields;
+mod history;
+mod item;
+mod json;
 mod matches;
+mod menu;
+mod pool;
+mod reader;
 mod ui;
 mod xmini;
 
 use ui::UI;
-use matches::simple_match;
-use matches::fuzzy_match;
-use matches::dmenu_match;
-use std::str::FromStr;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::fs::File;
+use bindings::Bindings;
+use config::{config_dir, ConfigFile, Layers, Value};
+use error::RumenuError;
+use fields::{parse_field_list, unescape_delimiter, Fields};
+use history::History;
+use item::Format;
+use json::Json;
+use xmini::{parse_window_id, Geometry, Monitor, Width};
+use matches::{CaseMode, MatcherRegistry};
+use menu::{MenuState, Outcome};
+use reader::ItemReader;
+use std::io::Write;
 use std::env;
+use std::path::Path;
 use std::iter::Iterator;
 
 use getopts::Options;
 
 const VERSION: &'static str = "0.0.1";
 
+/// Exit status when the input text is accepted instead of an item.
+const EXIT_INPUT: i32 = 10;
+
 pub struct Settings {
     topbar: bool,
-    casesensitive: bool,
+    monitor: Monitor,
+    geometry: Geometry,
+    embed: Option<u64>,
+    grab_pointer: bool,
+    case_mode: CaseMode,
     lines: u32,
     prompt: String,
-    font: String,
+    fonts: Vec<String>,
     normbgcolor: String,
     normfgcolor: String,
     selbgcolor: String,
     selfgcolor: String,
+    bordercolor: String,
+    hlbgcolor: String,
+    hlfgcolor: String,
+    selhlbgcolor: String,
+    selhlfgcolor: String,
     cache_file: String,
+    history_file: Option<String>,
+    format: Format,
     matcher: String,
+    multi: bool,
+    fast: bool,
+    bindings: Bindings,
 }
 
-struct Status {
-    text: String,
-    matches: Vec<String>,
-    items: Vec<String>,
-    selected: String,
-    page: u32,
-    settings: Settings,
+/// Settings that can come from the config file or the environment, with
+/// the option that sets them on the command line.
+const SETTINGS: &'static [(&'static str, &'static str)] = &[
+    ("lines", "l"),
+    ("prompt", "p"),
+    ("matcher", "matcher"),
+    ("font", "font"),
+    ("background", "background"),
+    ("foreground", "foreground"),
+    ("sbackground", "sbackground"),
+    ("sforeground", "sforeground"),
+    ("hlbackground", "hlbackground"),
+    ("hlforeground", "hlforeground"),
+    ("shlbackground", "shlbackground"),
+    ("shlforeground", "shlforeground"),
+    ("cache", "c"),
+    ("history", "history"),
+    ("delimiter", "delimiter"),
+    ("with_nth", "with-nth"),
+    ("accept_nth", "accept-nth"),
+    ("format", "format"),
+    ("monitor", "m"),
+    ("x", "x"),
+    ("y", "y"),
+    // -w embeds into a window like in dmenu, so the width is -z
+    ("width", "z"),
+    ("border", "border"),
+    ("bordercolor", "bordercolor"),
+    ("embed", "w"),
+];
+
+/// Keys of the settings that can come from the config file or the environment.
+fn setting_keys() -> Vec<&'static str> {
+    let mut keys: Vec<&str> = SETTINGS.iter().map(|&(key, _)| key).collect();
+    keys.push("position");
+    keys.push("case");
+    keys.push("multi");
+    keys.push("fast");
+    keys.push("center");
+    keys.push("grab_pointer");
+    keys
 }
 
-fn readitems(settings: &Settings) -> Vec<String> {
-    let mut items = vec![];
-    let input_items: Vec<_>;
+/// Collects the settings from the config file, the environment and the
+/// command line, each one overriding the previous.
+fn collect_settings(matches: &getopts::Matches, config: &Option<ConfigFile>, profile: Option<&str>) -> Layers {
+    let mut layers = Layers::new();
 
-    if settings.cache_file == "-" {
-        let stdin = std::io::stdin();
-        input_items = stdin.lock().lines().collect();
-    } else {
-        input_items = match File::open(settings.cache_file.clone()) {
-            Ok(file) => BufReader::new(file).lines().collect(),
-            Err(e) => panic!("{}", e)
+    if let Some(ref config) = *config {
+        for entry in config.settings(profile) {
+            layers.set(&entry.key, entry.value.clone(), config.origin(entry));
+        }
+    }
+
+    for key in setting_keys() {
+        let name = format!("RUMENU_{}", key.to_uppercase());
+        if let Ok(value) = env::var(&name) {
+            layers.set(key, Value::String(value), name);
+        }
+    }
+
+    for &(key, opt) in SETTINGS {
+        let values = matches.opt_strs(opt);
+        if values.len() > 0 {
+            let origin = if opt.len() == 1 { format!("-{}", opt) } else { format!("--{}", opt) };
+            if values.len() == 1 {
+                layers.set(key, Value::String(values[0].clone()), origin);
+            } else {
+                layers.set(key, Value::Array(values), origin);
+            }
+        }
+    }
+    if matches.opt_present("f") {
+        layers.set("fast", Value::Boolean(true), "-f".to_string());
+    }
+    if matches.opt_present("center") {
+        layers.set("center", Value::Boolean(true), "--center".to_string());
+    }
+    if matches.opt_present("grab-pointer") {
+        layers.set("grab_pointer", Value::Boolean(true), "--grab-pointer".to_string());
+    }
+    if matches.opt_present("multi") {
+        layers.set("multi", Value::Boolean(true), "--multi".to_string());
+    }
+    if matches.opt_present("b") {
+        layers.set("position", Value::String("bottom".to_string()), "-b".to_string());
+    }
+    if matches.opt_present("i") {
+        layers.set("case", Value::String("insensitive".to_string()), "-i".to_string());
+    } else if matches.opt_present("s") {
+        layers.set("case", Value::String("smart".to_string()), "-s".to_string());
+    }
+    layers
+}
+
+fn load_bindings(matches: &getopts::Matches, config: &Option<ConfigFile>, profile: Option<&str>) -> Result<Bindings, String> {
+    let mut bindings = Bindings::new();
+
+    match matches.opt_str("bindings") {
+        Some(path) => bindings.load_file(&path)?,
+        None => {
+            let path = format!("{}/bindings", config_dir());
+            if Path::new(&path).exists() {
+                bindings.load_file(&path)?
+            }
         }
     }
-    for item in input_items {
-        items.push(item.unwrap())
+    if let Some(ref config) = *config {
+        for entry in config.bindings(profile) {
+            let action = match entry.value {
+                Value::String(ref action) => action,
+                _ => return Err(format!("{}: expected an action name for '{}'", config.origin(entry), entry.key))
+            };
+            if let Err(e) = bindings.parse_binding(&format!("{}={}", entry.key, action)) {
+                return Err(format!("{}: {}", config.origin(entry), e))
+            }
+        }
+    }
+    for binding in matches.opt_strs("bind") {
+        if let Err(e) = bindings.parse_binding(&binding) {
+            return Err(format!("--bind: {}", e))
+        }
+    }
+    Ok(bindings)
+}
+
+fn build_settings(matches: &getopts::Matches) -> Result<Settings, String> {
+    let profile = matches.opt_str("profile").or(env::var("RUMENU_PROFILE").ok());
+    let profile = profile.as_ref().map(|profile| &profile[..]);
+
+    let config_path = matches.opt_str("config").or(env::var("RUMENU_CONFIG").ok());
+    let config = match config_path {
+        Some(path) => Some(ConfigFile::load(&path)?),
+        None => {
+            let path = format!("{}/config.toml", config_dir());
+            if Path::new(&path).exists() { Some(ConfigFile::load(&path)?) } else { None }
+        }
+    };
+
+    if let Some(ref config) = config {
+        config.check(&setting_keys())?;
+    }
+
+    if let Some(profile) = profile {
+        let found = match config {
+            Some(ref config) => config.has_profile(profile),
+            None => false
+        };
+        if !found {
+            return Err(format!("unknown profile '{}'", profile))
+        }
+    }
+
+    let layers = collect_settings(matches, &config, profile);
+
+    let lines = layers.get_integer("lines")?.unwrap_or(0);
+    if lines < 0 {
+        return Err(format!("invalid number of lines: {}", lines))
+    }
+
+    let case_mode = match layers.get_choice("case", &["sensitive", "insensitive", "smart"])? {
+        Some(ref case) if case == "insensitive" => CaseMode::Insensitive,
+        Some(ref case) if case == "smart" => CaseMode::Smart,
+        _ => CaseMode::Sensitive
+    };
+
+    let position = layers.get_choice("position", &["top", "bottom"])?;
+
+    let monitor = match layers.get_string("monitor") {
+        Ok(Some(ref monitor)) if monitor == "focus" => Monitor::Focus,
+        Ok(Some(ref monitor)) if monitor == "pointer" => Monitor::Pointer,
+        // -m used to choose the matcher
+        Ok(Some(ref monitor)) if monitor.trim().parse::<i64>().is_err() => {
+            let origin = layers.origin("monitor").unwrap_or("monitor");
+            return Err(format!("{}: invalid monitor '{}', expected a number, focus or pointer (the matcher is now chosen with --matcher)", origin, monitor))
+        },
+        _ => match layers.get_integer("monitor")? {
+            Some(n) if n >= 0 => Monitor::Index(n as usize),
+            Some(n) => return Err(format!("invalid monitor: {}", n)),
+            None => Monitor::Focus
+        }
+    };
+
+    let border = layers.get_integer("border")?.unwrap_or(0);
+    if border < 0 {
+        return Err(format!("invalid border width: {}", border))
     }
-    items
+    // The width is a number of pixels or a percentage string
+    let width = match layers.get_string("width") {
+        Ok(Some(width)) => Some(Width::parse(&width)?),
+        _ => match layers.get_integer("width")? {
+            Some(width) => Some(Width::parse(&width.to_string())?),
+            None => None
+        }
+    };
+    let geometry = Geometry {
+        x: layers.get_integer("x")?.unwrap_or(0) as i32,
+        y: layers.get_integer("y")?.unwrap_or(0) as i32,
+        width: width,
+        center: layers.get_bool("center")?.unwrap_or(false),
+        border: border as u32,
+    };
+
+    let with_nth = match layers.get_string("with_nth")? {
+        Some(list) => Some(parse_field_list(&list)?),
+        None => None
+    };
+    let accept_nth = match layers.get_string("accept_nth")? {
+        Some(list) => Some(parse_field_list(&list)?),
+        None => None
+    };
+    let fields = if with_nth.is_some() || accept_nth.is_some() {
+        let delimiter = unescape_delimiter(&layers.get_string("delimiter")?.unwrap_or("\t".to_string()));
+        if delimiter.is_empty() {
+            return Err("the delimiter cannot be empty".to_string())
+        }
+        Some(Fields::new(delimiter, with_nth, accept_nth))
+    } else {
+        None
+    };
+    let format = match layers.get_choice("format", &["lines", "jsonl"])? {
+        Some(ref format) if format == "jsonl" => {
+            if fields.is_some() {
+                return Err("fields cannot be selected in the jsonl format".to_string())
+            }
+            Format::Jsonl
+        },
+        _ => Format::Lines(fields)
+    };
+
+    Ok(Settings {
+        topbar: position.map(|position| position == "top").unwrap_or(true),
+        monitor: monitor,
+        geometry: geometry,
+        embed: match layers.get_string("embed")? {
+            Some(id) => Some(parse_window_id(&id)?),
+            None => None
+        },
+        grab_pointer: layers.get_bool("grab_pointer")?.unwrap_or(false),
+        case_mode: case_mode,
+        lines: lines as u32,
+        prompt: layers.get_string("prompt")?.unwrap_or(String::new()),
+        matcher: layers.get_string("matcher")?.unwrap_or("simple".to_string()),
+        multi: layers.get_bool("multi")?.unwrap_or(false),
+        fast: layers.get_bool("fast")?.unwrap_or(false),
+        fonts: layers.get_strings("font")?.unwrap_or(vec!["monospace:size=10".to_string()]),
+        normbgcolor: layers.get_string("background")?.unwrap_or("rgb:22/22/22".to_string()),
+        normfgcolor: layers.get_string("foreground")?.unwrap_or("rgb:bb/bb/bb".to_string()),
+        selbgcolor: layers.get_string("sbackground")?.unwrap_or("rgb:00/55/77".to_string()),
+        selfgcolor: layers.get_string("sforeground")?.unwrap_or("rgb:ee/ee/ee".to_string()),
+        bordercolor: layers.get_string("bordercolor")?.unwrap_or("rgb:00/55/77".to_string()),
+        hlbgcolor: layers.get_string("hlbackground")?.unwrap_or("rgb:22/22/22".to_string()),
+        hlfgcolor: layers.get_string("hlforeground")?.unwrap_or("rgb:ee/aa/44".to_string()),
+        selhlbgcolor: layers.get_string("shlbackground")?.unwrap_or("rgb:00/55/77".to_string()),
+        selhlfgcolor: layers.get_string("shlforeground")?.unwrap_or("rgb:ff/cc/66".to_string()),
+        cache_file: layers.get_string("cache")?.unwrap_or("-".to_string()),
+        history_file: layers.get_string("history")?,
+        format: format,
+        bindings: load_bindings(matches, &config, profile)?,
+    })
 }
 
-fn parse_args(args: Vec<String>) -> (bool, Settings) {
+/// Parses the command line, returns `None` when there is nothing else to do.
+fn parse_args(args: Vec<String>) -> Result<Option<Settings>, RumenuError> {
     let mut opts = Options::new();
     opts.optflag("v", "version", "show version");
-    opts.optflag("b", "topbar", "show topbar");
+    opts.optflag("f", "fast", "grab the keyboard before reading the items, shown while they are read");
+    opts.optflag("b", "bottom", "show the bar at the bottom of the screen");
+    opts.optflag("", "center", "show the menu as a box in the middle of the monitor");
+    opts.optflag("", "grab-pointer", "close the menu on clicks outside of it");
     opts.optflag("h", "help", "show help");
     opts.optflag("i", "caseinsensitive", "activate case insensitive");
+    opts.optflag("s", "smartcase", "case insensitive unless the input has uppercase letters");
+    opts.optflag("", "multi", "mark several items with Ctrl+Return, print them all on accept");
 
+    opts.optopt("", "format", "read an item per line or a JSON object per line, and print the same", "lines|jsonl");
+    opts.optopt("", "delimiter", "field delimiter of the lines, a tab by default", "DELIMITER");
+    opts.optopt("", "with-nth", "fields shown and matched, like 2 or 1,3..", "FIELDS");
+    opts.optopt("", "accept-nth", "fields printed on accept", "FIELDS");
+    opts.optopt("m", "monitor", "show the menu on the monitor with this number, or on the one with the focus or the pointer", "N|focus|pointer");
+    opts.optopt("x", "", "horizontal offset of the window", "X");
+    opts.optopt("y", "", "vertical offset of the window, from the bottom with -b", "Y");
+    opts.optopt("z", "", "width of the window, in pixels or like 40% (not -w, which embeds)", "WIDTH");
+    opts.optopt("", "border", "border width", "PIXELS");
+    opts.optopt("", "bordercolor", "border color", "COLOR");
+    opts.optopt("w", "", "embed into the window with this id", "WINDOWID");
     opts.optopt("l", "lines", "lines of vertical list", "LINES");
     opts.optopt("c", "cache", "cache file with available commands", "CACHE_FILE");
     opts.optopt("p", "prompt", "add prompt to left of input field", "PROMPT");
-    opts.optopt("m", "matcher", "select matcher function", "simple|dmenu|fuzzy");
-    opts.optopt("", "font", "font or font set", "FONT");
+    opts.optopt("", "history", "rank the accepted items by frecency and keep them in FILE", "FILE");
+    opts.optopt("", "matcher", "select matcher function", "simple|dmenu|fuzzy");
+    opts.optmulti("", "font", "fontconfig pattern, repeat it to add fallback fonts", "FONT");
     opts.optopt("", "background", "normal background color", "NBG");
     opts.optopt("", "foreground", "normal foreground color", "NFG");
     opts.optopt("", "sbackground", "selected background color", "SBG");
     opts.optopt("", "sforeground", "selected foreground color", "SFG");
+    opts.optopt("", "hlbackground", "background color of the matched characters", "HLBG");
+    opts.optopt("", "hlforeground", "foreground color of the matched characters", "HLFG");
+    opts.optopt("", "shlbackground", "background color of the matched characters of the selected item", "SHLBG");
+    opts.optopt("", "shlforeground", "foreground color of the matched characters of the selected item", "SHLFG");
+    opts.optopt("", "bindings", "file with key bindings, one CHORD = ACTION per line", "FILE");
+    opts.optmulti("", "bind", "bind a key chord to an action", "CHORD=ACTION");
+    opts.optopt("", "config", "configuration file", "FILE");
+    opts.optopt("", "profile", "use the settings of a profile of the configuration file", "PROFILE");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
-        Err(f) => { panic!(f.to_string()) }
-    };
-
-    let lines = match matches.opt_str("l") {
-        Some(lines_str) => {
-            u32::from_str(lines_str.trim()).unwrap_or(0)
-        },
-        None => 0
-    };
-
-    let settings = Settings {
-        topbar: matches.opt_present("b"),
-        casesensitive: !matches.opt_present("i"),
-        lines: lines,
-        prompt:  matches.opt_str("p").unwrap_or(String::new()),
-        matcher:  matches.opt_str("m").unwrap_or("simple".to_string()),
-        font: matches.opt_str("font").unwrap_or("fixed".to_string()),
-        normbgcolor: matches.opt_str("background").unwrap_or("rgb:22/22/22".to_string()),
-        normfgcolor: matches.opt_str("foreground").unwrap_or("rgb:bb/bb/bb".to_string()),
-        selbgcolor: matches.opt_str("sbackground").unwrap_or("rgb:00/55/77".to_string()),
-        selfgcolor: matches.opt_str("sforeground").unwrap_or("rgb:ee/ee/ee".to_string()),
-        cache_file: matches.opt_str("cache").unwrap_or("-".to_string()),
+        Err(f) => { return Err(RumenuError::BadArguments(f.to_string())) }
     };
 
     let mut exit = false;
@@ -116,36 +396,80 @@ fn parse_args(args: Vec<String>) -> (bool, Settings) {
         print!("{}", opts.usage(&brief));
         exit = true
     }
-    (exit, settings)
+    if exit { return Ok(None) }
+
+    Ok(Some(build_settings(&matches)?))
 }
 
-fn main () {
-    let args: Vec<String> = env::args().collect();
+/// The query, the key that closed the menu and the objects of the accepted items.
+fn json_outcome(state: &MenuState, ids: &[usize], key: &str) -> Json {
+    let objects = ids.iter().map(|&id| state.object(id).cloned().unwrap_or(Json::String(state.value(id).to_string()))).collect();
+    Json::Object(vec![
+        ("query".to_string(), Json::String(state.text.clone())),
+        ("key".to_string(), Json::String(key.to_string())),
+        ("items".to_string(), Json::Array(objects)),
+    ])
+}
 
-    let (exit, settings) = parse_args(args);
-    if exit { return; }
+/// Runs the menu and returns the exit status.
+fn rumenu(args: Vec<String>) -> Result<i32, RumenuError> {
+    let settings = match parse_args(args)? {
+        Some(settings) => settings,
+        None => return Ok(0)
+    };
 
-    let mut ui = UI::new(&settings);
+    let matcher = MatcherRegistry::new().take(&settings.matcher)?;
 
-    let items = readitems(&settings);
+    // Like dmenu the keyboard is grabbed once every item is read, -f grabs
+    // it right away and shows the items while they are read
+    let mut reader = ItemReader::spawn(&settings.cache_file, settings.format.clone());
+    if !settings.fast {
+        reader.wait()?;
+    }
+    let mut ui = UI::new(&settings)?;
 
-    let mut status = Status {
-        text: "".to_string(),
-        matches: vec![],
-        items: items,
-        selected: "".to_string(),
-        page: 0,
-        settings: settings,
-    };
+    let mut state = MenuState::new(vec![], matcher, &settings);
+    if let Some(ref path) = settings.history_file {
+        state.set_history(&History::load(path)?, history::now());
+    }
 
-    if status.settings.matcher == "fuzzy" {
-        status.matches = fuzzy_match(&status.text, &status.items);
-    } else if status.settings.matcher == "dmenu" {
-        status.matches = dmenu_match(&status.text, &status.items);
+    let (ids, lines, status) = match ui.run(&mut state, &mut reader, &settings.bindings)? {
+        Outcome::Selected(ids) => {
+            let lines = ids.iter().map(|&id| state.value(id).to_string()).collect();
+            (ids, lines, 0)
+        },
+        Outcome::Input(text) => (vec![], vec![text], EXIT_INPUT),
+        Outcome::Cancelled => return Err(RumenuError::Cancelled),
+    };
+    if settings.format == Format::Jsonl {
+        println!("{}", json_outcome(&state, &ids, ui.close_key()));
     } else {
-        status.matches = simple_match(&status.text, &status.items);
+        for line in lines.iter() {
+            println!("{}", line);
+        }
     }
 
-    status.selected = status.matches.first().unwrap_or(&"".to_string()).clone();
-    ui.run(status);
+    // The choice is already printed, a history that cannot be written only deserves a warning
+    if let Some(ref path) = settings.history_file {
+        if let Err(e) = History::record(path, &lines) {
+            writeln!(std::io::stderr(), "rumenu: cannot write {}: {}", path, e).unwrap();
+        }
+    }
+    Ok(status)
+}
+
+fn main () {
+    let args: Vec<String> = env::args().collect();
+
+    match rumenu(args) {
+        Ok(0) => (),
+        Ok(status) => std::process::exit(status),
+        Err(e) => {
+            match e {
+                RumenuError::Cancelled => (),
+                _ => { writeln!(std::io::stderr(), "rumenu: {}", e).unwrap(); }
+            }
+            std::process::exit(e.exit_code());
+        }
+    }
 }
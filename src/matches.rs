@@ -1,122 +1,663 @@
-pub fn simple_match(text: &String, items: &Vec<String>) -> Vec<String> {
-    let mut matches = vec![];
+use std::cmp::{max, Ordering};
+use std::borrow::Cow;
 
-    for item in items {
-        match item.find(text) {
-            Some(position) => matches.push((item.clone(), position as f64)),
-            None => continue
+/// How the letter case of the input and the items is compared.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the input has an uppercase letter.
+    Smart,
+}
+
+impl CaseMode {
+    /// Whether matching `text` should ignore case.
+    pub fn ignores_case(&self, text: &str) -> bool {
+        match *self {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !text.chars().any(|c| c.is_uppercase()),
         }
     }
-    let mut results = vec![];
+}
 
-    matches.sort_by(|x, y| x.1.partial_cmp(&y.1).unwrap());
+/// Lowercases `text` using the Unicode case mappings when `fold` is set,
+/// only allocating when something changes. This is not full case folding:
+/// "SS" lowercases to "ss", which does not match "ß".
+pub fn fold_case<'a>(text: &'a str, fold: bool) -> Cow<'a, str> {
+    if !fold || (text.is_ascii() && !text.bytes().any(|b| b.is_ascii_uppercase())) {
+        Cow::Borrowed(text)
+    } else if text.is_ascii() {
+        Cow::Owned(text.to_ascii_lowercase())
+    } else {
+        Cow::Owned(text.chars().flat_map(|c| c.to_lowercase()).collect())
+    }
+}
 
-    for result_item in matches {
-        results.push(result_item.0);
+/// Like `fold_case`, also returning for every byte of the folded text the
+/// byte range of the original character it comes from.
+fn fold_case_with_offsets(text: &str, fold: bool) -> (String, Vec<(usize, usize)>) {
+    let mut folded = String::new();
+    let mut offsets = vec![];
+    for (position, c) in text.char_indices() {
+        let start = folded.len();
+        if fold {
+            folded.extend(c.to_lowercase());
+        } else {
+            folded.push(c);
+        }
+        for _ in start..folded.len() {
+            offsets.push((position, position + c.len_utf8()));
+        }
     }
-    results
+    (folded, offsets)
 }
 
-pub fn dmenu_match(text: &String, items: &Vec<String>) -> Vec<String> {
-    let mut matches_exact = vec![];
-    let mut matches_prefix = vec![];
-    let mut matches_substring = vec![];
+/// Computes spans on the case folded text and item, and maps them back to byte ranges of `item`.
+fn folded_highlight(text: &String, item: &String, case: CaseMode, spans: fn(&str, &str) -> Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let fold = case.ignores_case(text);
+    let (folded_item, offsets) = fold_case_with_offsets(item, fold);
+    spans(&fold_case(text, fold), &folded_item).iter()
+        .map(|&(start, end)| (offsets[start].0, offsets[end - 1].1))
+        .collect()
+}
 
-    if text.len() == 0 { return items.clone() }
+fn simple_spans(text: &str, item: &str) -> Vec<(usize, usize)> {
+    match item.find(text) {
+        Some(position) if text.len() > 0 => vec![(position, position + text.len())],
+        _ => vec![]
+    }
+}
 
-    let words: Vec<&str> = text.split_whitespace().collect();
-    for item in items {
-        let mut exact = false;
-        let mut prefix = false;
-        let mut substring = false;
+fn dmenu_spans(text: &str, item: &str) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    for word in text.split_whitespace() {
+        if let Some(position) = item.find(word) {
+            spans.push((position, position + word.len()));
+        }
+    }
+    spans.sort();
+    spans
+}
 
-        for word in &words {
-            match item.find(word) {
-                Some(0) => {
-                    if word == item { exact = true }
-                    else { prefix = true }
-                },
-                Some(_) => substring = true,
-                None => {
-                    exact = false;
-                    prefix = false;
-                    substring = false;
-                    break
-                }
-            }
+// Fuzzy scores, the same as fzf uses
+const SCORE_MATCH: i32 = 16;
+const SCORE_GAP_START: i32 = -3;
+const SCORE_GAP_EXTENSION: i32 = -1;
+const BONUS_BOUNDARY: i32 = SCORE_MATCH / 2;
+const BONUS_BOUNDARY_WHITE: i32 = BONUS_BOUNDARY + 2;
+const BONUS_BOUNDARY_DELIMITER: i32 = BONUS_BOUNDARY + 1;
+const BONUS_NON_WORD: i32 = SCORE_MATCH / 2;
+const BONUS_CAMEL: i32 = BONUS_BOUNDARY + SCORE_GAP_EXTENSION;
+const BONUS_CONSECUTIVE: i32 = -(SCORE_GAP_START + SCORE_GAP_EXTENSION);
+const BONUS_FIRST_CHAR_MULTIPLIER: i32 = 2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+    White,
+    Delimiter,
+    NonWord,
+    Lower,
+    Upper,
+    Letter,
+    Number,
+}
+
+impl CharClass {
+    fn of(c: char) -> CharClass {
+        if c.is_lowercase() {
+            CharClass::Lower
+        } else if c.is_uppercase() {
+            CharClass::Upper
+        } else if c.is_numeric() {
+            CharClass::Number
+        } else if c.is_alphabetic() {
+            CharClass::Letter
+        } else if c.is_whitespace() {
+            CharClass::White
+        } else if "/,:;|".contains(c) {
+            CharClass::Delimiter
+        } else {
+            CharClass::NonWord
         }
+    }
 
-        if exact {
-            matches_exact.push(item.clone())
-        } else if prefix {
-            matches_prefix.push(item.clone())
-        } else if substring {
-            matches_substring.push(item.clone())
+    fn is_word(&self) -> bool {
+        matches!(*self, CharClass::Lower | CharClass::Upper | CharClass::Letter | CharClass::Number)
+    }
+}
+
+/// Bonus for matching a character of `class` that follows one of `prev`:
+/// word starts, camelCase humps and digits after letters are worth more.
+fn bonus_for(prev: CharClass, class: CharClass) -> i32 {
+    if class.is_word() {
+        match prev {
+            CharClass::White => return BONUS_BOUNDARY_WHITE,
+            CharClass::Delimiter => return BONUS_BOUNDARY_DELIMITER,
+            CharClass::NonWord => return BONUS_BOUNDARY,
+            _ => ()
         }
     }
-    let mut results = vec![];
-    results.extend(matches_exact);
-    results.extend(matches_prefix);
-    results.extend(matches_substring);
-    results
+    if (prev == CharClass::Lower && class == CharClass::Upper) || (prev != CharClass::Number && class == CharClass::Number) {
+        return BONUS_CAMEL
+    }
+    match class {
+        CharClass::NonWord | CharClass::Delimiter => BONUS_NON_WORD,
+        CharClass::White => BONUS_BOUNDARY_WHITE,
+        _ => 0
+    }
+}
+
+/// Scores `text` as a subsequence of `item` choosing the best alignment,
+/// like fzf does: every matched character scores, with bonuses for word
+/// boundaries, camelCase, consecutive runs and the start of the item, and
+/// penalties for the gaps between them.
+///
+/// Returns the score and the byte ranges of `item` that matched.
+pub fn fuzzy_positions(text: &String, item: &String, case: CaseMode) -> Option<(f64, Vec<(usize, usize)>)> {
+    fuzzy_align(text, item, case, true)
 }
 
-pub fn fuzzy_match(text: &String, items: &Vec<String>) -> Vec<String> {
-    fn fuzzy_find_match(text: &String, item: &String) -> (String, f64) {
-        let mut score = 1.0;
-        let mut item_copy = item.clone();
+/// The work of `fuzzy_positions`. Without `positions` only two rows of the
+/// table are kept and no ranges are returned, which is all ranking needs.
+fn fuzzy_align(text: &String, item: &String, case: CaseMode, positions: bool) -> Option<(f64, Vec<(usize, usize)>)> {
+    let fold = case.ignores_case(text);
+    let pattern: Vec<char> = fold_case(text, fold).chars().collect();
+    if pattern.is_empty() {
+        return Some((0.0, vec![]))
+    }
+
+    // Cheap check before scoring
+    let mut next = 0;
+    for c in item.chars() {
+        if fold {
+            for c in c.to_lowercase() {
+                if next < pattern.len() && c == pattern[next] { next += 1 }
+            }
+        } else if next < pattern.len() && c == pattern[next] {
+            next += 1
+        }
+    }
+    if next < pattern.len() {
+        return None
+    }
+
+    // Folded characters of the item with the original byte range they come
+    // from, and the bonus for matching them
+    let mut chars: Vec<(char, (usize, usize))> = Vec::with_capacity(item.len());
+    let mut bonus: Vec<i32> = Vec::with_capacity(item.len());
+    let mut prev_class = CharClass::White;
+    for (position, c) in item.char_indices() {
+        let class = CharClass::of(c);
+        let range = (position, position + c.len_utf8());
+        if fold {
+            for folded in c.to_lowercase() {
+                chars.push((folded, range));
+                bonus.push(bonus_for(prev_class, class));
+            }
+        } else {
+            chars.push((c, range));
+            bonus.push(bonus_for(prev_class, class));
+        }
+        prev_class = class;
+    }
+
+    // score[row(i) + j] is the best score of the first i + 1 pattern characters
+    // with the last one matched at j, `from` the position of the one before
+    let n = chars.len();
+    let rows = if positions { pattern.len() } else { 2 };
+    let row = |i: usize| (i % rows) * n;
+    let unmatched = i32::MIN / 2;
+    let mut score = vec![unmatched; n * rows];
+    let mut from = if positions { vec![0; n * pattern.len()] } else { vec![] };
+    // Length of the consecutive run ending at j and the bonus of its first character
+    let mut run = vec![(0, 0); n * rows];
 
-        for c in text.chars() {
-            score += match item_copy.find(c) {
-                Some(position) => (10.0 - position as f64),
-                None => {
-                    score = 0.0;
-                    break
+    for j in 0..n {
+        if chars[j].0 == pattern[0] {
+            score[j] = SCORE_MATCH + bonus[j] * BONUS_FIRST_CHAR_MULTIPLIER;
+            run[j] = (1, bonus[j]);
+        }
+    }
+    for i in 1..pattern.len() {
+        let (above, current) = (row(i - 1), row(i));
+        // The row may still hold the scores of i - 2
+        for j in 0..n {
+            score[current + j] = unmatched;
+        }
+        // Best previous score ending at least two characters before j, with its gap penalty
+        let mut gap = unmatched;
+        let mut gap_from = 0;
+        for j in i..n {
+            if j >= 2 && score[above + j - 2] > unmatched && score[above + j - 2] + SCORE_GAP_START >= gap + SCORE_GAP_EXTENSION {
+                gap = score[above + j - 2] + SCORE_GAP_START;
+                gap_from = j - 2;
+            } else if gap > unmatched {
+                gap += SCORE_GAP_EXTENSION;
+            }
+            if chars[j].0 != pattern[i] {
+                continue
+            }
+
+            if gap > unmatched {
+                score[current + j] = gap + SCORE_MATCH + bonus[j];
+                if positions { from[i * n + j] = gap_from }
+                run[current + j] = (1, bonus[j]);
+            }
+            if score[above + j - 1] > unmatched {
+                let (length, first_bonus) = run[above + j - 1];
+                let (consecutive, b) = if bonus[j] >= BONUS_BOUNDARY && bonus[j] > first_bonus {
+                    // A new word starts, count it as a new run
+                    ((1, bonus[j]), bonus[j])
+                } else {
+                    ((length + 1, first_bonus), *[bonus[j], BONUS_CONSECUTIVE, first_bonus].iter().max().unwrap())
+                };
+                let diagonal = score[above + j - 1] + SCORE_MATCH + b;
+                if diagonal >= score[current + j] {
+                    score[current + j] = diagonal;
+                    if positions { from[i * n + j] = j - 1 }
+                    run[current + j] = consecutive;
                 }
-            };
-            item_copy = item_copy.chars().skip_while(|&x| x != c).collect()
+            }
         }
+    }
 
-        return (item.clone(), score)
+    let last = pattern.len() - 1;
+    let scores = &score[row(last)..row(last) + n];
+    let mut best = None;
+    for j in last..n {
+        if scores[j] > unmatched && best.map(|b| scores[j] > scores[b]).unwrap_or(true) {
+            best = Some(j);
+        }
+    }
+    let mut j = best?;
+    let total = scores[j];
+    if !positions {
+        return Some((total as f64, vec![]))
     }
 
-    let mut matches = vec![];
+    let mut matched = vec![j];
+    for i in (1..pattern.len()).rev() {
+        j = from[i * n + j];
+        matched.push(j);
+    }
+    matched.reverse();
 
-    for item in items {
-        let item_match = fuzzy_find_match(text, item);
-        if item_match.1 > 0.0 {
-            matches.push(item_match);
+    // Merge the characters into ranges of the original item
+    let mut spans: Vec<(usize, usize)> = vec![];
+    for position in matched {
+        let (start, end) = chars[position].1;
+        match spans.last_mut() {
+            Some(span) if span.1 >= start => { span.1 = max(span.1, end); continue },
+            _ => ()
         }
+        spans.push((start, end));
+    }
+    Some((total as f64, spans))
+}
+
+/// A matcher scores the items against the input text.
+///
+/// New matchers are made available to `--matcher` by adding them to a `MatcherRegistry`.
+pub trait Matcher: Send + Sync {
+    /// Name used to select the matcher from the command line.
+    fn name(&self) -> &'static str;
+
+    /// Scores `item` against `text`, higher is better, `None` when it does not match.
+    fn score(&self, text: &String, item: &String, case: CaseMode) -> Option<f64>;
+
+    /// Whether an item matching a text always matches the texts it starts
+    /// with, so a growing text only has to look at the previous matches.
+    fn narrows(&self) -> bool {
+        false
+    }
+
+    /// What an item accepted with `frecency` from the history adds to its
+    /// score. It grows slowly so that heavy use does not bury much better
+    /// matches.
+    fn boost(&self, frecency: f64) -> f64 {
+        frecency.ln_1p()
+    }
+
+    /// Returns the byte ranges `(start, end)` of `item` matched by `text`.
+    fn highlight(&self, _text: &String, _item: &String, _case: CaseMode) -> Vec<(usize, usize)> {
+        vec![]
+    }
+}
+
+pub struct SimpleMatcher;
+
+impl Matcher for SimpleMatcher {
+    fn name(&self) -> &'static str { "simple" }
+
+    fn score(&self, text: &String, item: &String, case: CaseMode) -> Option<f64> {
+        simple_score(text, item, case)
+    }
+
+    fn narrows(&self) -> bool { true }
+
+    /// Scores are positions, a few recent uses move an item a character or two.
+    fn boost(&self, frecency: f64) -> f64 {
+        frecency.ln_1p() / 2.0
+    }
+
+    fn highlight(&self, text: &String, item: &String, case: CaseMode) -> Vec<(usize, usize)> {
+        folded_highlight(text, item, case, simple_spans)
+    }
+}
+
+pub struct DmenuMatcher;
+
+impl Matcher for DmenuMatcher {
+    fn name(&self) -> &'static str { "dmenu" }
+
+    fn score(&self, text: &String, item: &String, case: CaseMode) -> Option<f64> {
+        dmenu_score(text, item, case)
+    }
+
+    fn narrows(&self) -> bool { true }
+
+    /// Less than one, so exact, prefix and substring matches stay apart and
+    /// are ranked by frecency inside.
+    fn boost(&self, frecency: f64) -> f64 {
+        frecency / (1.0 + frecency)
+    }
+
+    fn highlight(&self, text: &String, item: &String, case: CaseMode) -> Vec<(usize, usize)> {
+        folded_highlight(text, item, case, dmenu_spans)
     }
-    let mut results = vec![];
+}
+
+pub struct FuzzyMatcher;
+
+impl Matcher for FuzzyMatcher {
+    fn name(&self) -> &'static str { "fuzzy" }
 
-    matches.sort_by(|x, y| y.1.partial_cmp(&x.1).unwrap());
+    fn score(&self, text: &String, item: &String, case: CaseMode) -> Option<f64> {
+        fuzzy_score(text, item, case)
+    }
+
+    fn narrows(&self) -> bool { true }
 
-    for result_item in matches {
-        results.push(result_item.0);
+    /// A use in the last hour is worth about a matched character and a half.
+    fn boost(&self, frecency: f64) -> f64 {
+        SCORE_MATCH as f64 * frecency.ln_1p()
     }
-    results
+
+    fn highlight(&self, text: &String, item: &String, case: CaseMode) -> Vec<(usize, usize)> {
+        fuzzy_positions(text, item, case).map(|(_, spans)| spans).unwrap_or(vec![])
+    }
+}
+
+/// Set of matchers that can be selected by name.
+pub struct MatcherRegistry {
+    matchers: Vec<Box<dyn Matcher>>,
+}
+
+impl MatcherRegistry {
+    /// Creates a registry with the builtin matchers.
+    pub fn new() -> MatcherRegistry {
+        let mut registry = MatcherRegistry { matchers: vec![] };
+        registry.register(Box::new(SimpleMatcher));
+        registry.register(Box::new(DmenuMatcher));
+        registry.register(Box::new(FuzzyMatcher));
+        registry
+    }
+
+    /// Adds a matcher, replacing any registered matcher with the same name.
+    pub fn register(&mut self, matcher: Box<dyn Matcher>) {
+        self.matchers.retain(|m| m.name() != matcher.name());
+        self.matchers.push(matcher);
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.matchers.iter().map(|m| m.name()).collect()
+    }
+
+    /// Takes the matcher called `name` out of the registry.
+    pub fn take(mut self, name: &str) -> Result<Box<dyn Matcher>, String> {
+        match self.matchers.iter().position(|m| m.name() == name) {
+            Some(n) => Ok(self.matchers.swap_remove(n)),
+            None => Err(format!("unknown matcher '{}', valid matchers are: {}", name, self.names().join(", ")))
+        }
+    }
+}
+
+/// Scores the `candidates` of `items`, the matches keep the order of `candidates`.
+pub fn score_items<M: Matcher + ?Sized>(matcher: &M, text: &String, items: &[String], candidates: &[usize], case: CaseMode) -> Vec<(usize, f64)> {
+    let mut scored = vec![];
+    for &n in candidates {
+        if let Some(score) = matcher.score(text, &items[n], case) {
+            scored.push((n, score));
+        }
+    }
+    scored
+}
+
+/// Orders the scores `x` and `y` best first, a NaN score comes last.
+pub fn best_first(x: f64, y: f64) -> Ordering {
+    let worst_if_nan = |score: f64| if score.is_nan() { f64::NEG_INFINITY } else { score };
+    worst_if_nan(y).total_cmp(&worst_if_nan(x))
+}
+
+/// Sorts scored matches best first, equal scores keep their order.
+pub fn sort_scored(scored: &mut Vec<(usize, f64)>) {
+    scored.sort_by(|x, y| best_first(x.1, y.1));
+}
+
+/// The earlier the text appears in the item, the better.
+pub fn simple_score(text: &String, item: &String, case: CaseMode) -> Option<f64> {
+    let fold = case.ignores_case(text);
+    fold_case(item, fold).find(&*fold_case(text, fold)).map(|position| -(position as f64))
+}
+
+/// Like dmenu, every word must appear in the item, exact matches go first,
+/// then prefix matches and then substring matches.
+pub fn dmenu_score(text: &String, item: &String, case: CaseMode) -> Option<f64> {
+    if text.len() == 0 { return Some(0.0) }
+
+    let fold = case.ignores_case(text);
+    let text = fold_case(text, fold);
+    let folded_item = fold_case(item, fold);
+    let mut exact = false;
+    let mut prefix = false;
+    let mut substring = false;
+
+    for word in text.split_whitespace() {
+        match folded_item.find(word) {
+            Some(0) => {
+                if word == folded_item { exact = true }
+                else { prefix = true }
+            },
+            Some(_) => substring = true,
+            None => return None
+        }
+    }
+
+    if exact {
+        Some(3.0)
+    } else if prefix {
+        Some(2.0)
+    } else if substring {
+        Some(1.0)
+    } else {
+        None
+    }
+}
+
+pub fn fuzzy_score(text: &String, item: &String, case: CaseMode) -> Option<f64> {
+    fuzzy_align(text, item, case, false).map(|(score, _)| score)
+}
+
+/// Indices of the items matching `text`, best match first, ranked on a
+/// single thread to check the pool against.
+#[cfg(test)]
+pub fn filter<M: Matcher + ?Sized>(matcher: &M, text: &String, items: &[String], case: CaseMode) -> Vec<usize> {
+    let candidates: Vec<usize> = (0..items.len()).collect();
+    let mut scored = score_items(matcher, text, items, &candidates, case);
+    sort_scored(&mut scored);
+    scored.into_iter().map(|(n, _)| n).collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::simple_match;
+    use super::{filter, fuzzy_positions, fuzzy_score, fold_case, sort_scored, CaseMode, Matcher, MatcherRegistry, DmenuMatcher, FuzzyMatcher, SimpleMatcher};
+
+    fn simple_match(text: &String, items: &Vec<String>, case: CaseMode) -> Vec<usize> {
+        filter(&SimpleMatcher, text, items, case)
+    }
+
+    fn dmenu_match(text: &String, items: &Vec<String>, case: CaseMode) -> Vec<usize> {
+        filter(&DmenuMatcher, text, items, case)
+    }
+
+    fn fuzzy_match(text: &String, items: &Vec<String>, case: CaseMode) -> Vec<usize> {
+        filter(&FuzzyMatcher, text, items, case)
+    }
 
     #[test]
     fn test_simple_match_normal_case() {
-        let match_results = simple_match(&"test".to_string(), &vec!["atest".to_string(), "test".to_string(), "testa".to_string(), "nomatch".to_string()]);
+        let items = vec!["atest".to_string(), "test".to_string(), "testa".to_string(), "nomatch".to_string()];
+        let match_results = simple_match(&"test".to_string(), &items, CaseMode::Sensitive);
         assert!(match_results.len() == 3);
-        assert!(match_results[0] == "test".to_string());
-        assert!(match_results[1] == "testa".to_string());
-        assert!(match_results[2] == "atest".to_string());
+        assert!(items[match_results[0]] == "test".to_string());
+        assert!(items[match_results[1]] == "testa".to_string());
+        assert!(items[match_results[2]] == "atest".to_string());
     }
 
     #[test]
     fn test_simple_match_no_matches() {
-        let match_results = simple_match(&"bad-search".to_string(), &vec!["test1".to_string(), "test2".to_string(), "test3".to_string()]);
+        let match_results = simple_match(&"bad-search".to_string(), &vec!["test1".to_string(), "test2".to_string(), "test3".to_string()], CaseMode::Sensitive);
         assert!(match_results.len() == 0);
     }
 
+    #[test]
+    fn test_registry_take_builtin() {
+        let matcher = MatcherRegistry::new().take("fuzzy").unwrap();
+        assert!(matcher.name() == "fuzzy");
+    }
+
+    #[test]
+    fn test_registry_unknown_matcher_lists_names() {
+        let error = MatcherRegistry::new().take("nope").err().unwrap();
+        assert!(error == "unknown matcher 'nope', valid matchers are: simple, dmenu, fuzzy");
+    }
+
+    #[test]
+    fn test_registry_register_custom_matcher() {
+        struct PrefixMatcher;
+        impl Matcher for PrefixMatcher {
+            fn name(&self) -> &'static str { "prefix" }
+            fn score(&self, text: &String, item: &String, _case: CaseMode) -> Option<f64> {
+                if item.starts_with(text.as_str()) { Some(0.0) } else { None }
+            }
+        }
+
+        let mut registry = MatcherRegistry::new();
+        registry.register(Box::new(PrefixMatcher));
+        let matcher = registry.take("prefix").unwrap();
+        let match_results = filter(&*matcher, &"te".to_string(), &vec!["atest".to_string(), "test".to_string()], CaseMode::Sensitive);
+        assert!(match_results == vec![1]);
+    }
+
+    #[test]
+    fn test_highlight_spans() {
+        assert!(SimpleMatcher.highlight(&"est".to_string(), &"atest".to_string(), CaseMode::Sensitive) == vec![(2, 5)]);
+        assert!(FuzzyMatcher.highlight(&"ts".to_string(), &"atest".to_string(), CaseMode::Sensitive) == vec![(1, 2), (3, 4)]);
+    }
+
+    #[test]
+    fn test_case_insensitive_matching() {
+        let items = vec!["Firefox".to_string(), "ÉCLAIR".to_string(), "gimp".to_string()];
+        assert!(simple_match(&"fire".to_string(), &items, CaseMode::Sensitive).len() == 0);
+        assert!(simple_match(&"fire".to_string(), &items, CaseMode::Insensitive) == vec![0]);
+        assert!(dmenu_match(&"éclair".to_string(), &items, CaseMode::Insensitive) == vec![1]);
+        assert!(fuzzy_match(&"GMP".to_string(), &items, CaseMode::Insensitive) == vec![2]);
+    }
+
+    #[test]
+    fn test_smart_case_matching() {
+        let items = vec!["Firefox".to_string(), "firefox-esr".to_string()];
+        assert!(simple_match(&"fire".to_string(), &items, CaseMode::Smart).len() == 2);
+        assert!(simple_match(&"Fire".to_string(), &items, CaseMode::Smart) == vec![0]);
+    }
+
+    #[test]
+    fn test_fold_case_is_unicode_aware() {
+        assert!(fold_case("ÀÉÎ ΣΑΣ", true) == "àéî σασ");
+        assert!(fold_case("ÀÉÎ", false) == "ÀÉÎ");
+    }
+
+    #[test]
+    fn test_fold_case_is_not_full_folding() {
+        assert!(fold_case("STRASSE", true) == "strasse");
+        assert!(fold_case("Straße", true) == "straße");
+    }
+
+    #[test]
+    fn test_highlight_maps_folded_offsets_back() {
+        // 'İ' lowercases to two chars, the span must still cover the original bytes
+        let item = "İstanbul".to_string();
+        assert!(SimpleMatcher.highlight(&"stan".to_string(), &item, CaseMode::Insensitive) == vec![(2, 6)]);
+        assert!(SimpleMatcher.highlight(&"i̇s".to_string(), &item, CaseMode::Insensitive) == vec![(0, 3)]);
+    }
+
+    fn commands() -> Vec<String> {
+        ["firefox", "ffmpeg", "diff", "gnome-font-viewer", "xdg-open", "git-flow", "libreoffice", "FontForge",
+         "gnome-terminal", "xterm", "terminator", "virt-manager", "vim", "nvim", "gvim", "ssh", "ssh-agent"]
+            .iter().map(|item| item.to_string()).collect()
+    }
+
+    fn fuzzy_ranking(text: &str) -> Vec<String> {
+        let items = commands();
+        fuzzy_match(&text.to_string(), &items, CaseMode::Smart).iter().map(|&n| items[n].clone()).collect()
+    }
+
+    #[test]
+    fn test_nan_scores_sort_last() {
+        let mut scored = vec![(0, f64::NAN), (1, 1.0), (2, 3.0), (3, f64::NAN), (4, 2.0)];
+        sort_scored(&mut scored);
+        assert!(scored.iter().map(|x| x.0).collect::<Vec<_>>() == vec![2, 4, 1, 0, 3]);
+    }
+
+    #[test]
+    fn test_fuzzy_ranking() {
+        assert!(fuzzy_ranking("ff") == vec!["ffmpeg", "FontForge", "firefox", "diff", "libreoffice"]);
+        assert!(fuzzy_ranking("term") == vec!["terminator", "gnome-terminal", "xterm"]);
+        assert!(fuzzy_ranking("gt")[..2] == ["gnome-terminal", "git-flow"]);
+        assert!(fuzzy_ranking("vim") == vec!["vim", "virt-manager", "nvim", "gvim"]);
+        assert!(fuzzy_ranking("ssha") == vec!["ssh-agent"]);
+    }
+
+    #[test]
+    fn test_fuzzy_score_agrees_with_positions() {
+        let items = ["gnome-terminal", "FontForge", "libreoffice", "a_b_c_abc", "xterm", "ssh-agent", "aaaaab"];
+        for text in &["ff", "term", "gt", "abc", "ssha", "ab", "nope"] {
+            for item in items.iter() {
+                let (text, item) = (text.to_string(), item.to_string());
+                let score = fuzzy_positions(&text, &item, CaseMode::Smart).map(|(score, _)| score);
+                assert!(fuzzy_score(&text, &item, CaseMode::Smart) == score);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_positions() {
+        let positions = fuzzy_positions(&"ssha".to_string(), &"ssh-agent".to_string(), CaseMode::Sensitive);
+        assert!(positions.unwrap().1 == vec![(0, 3), (4, 5)]);
+        // The word start after '_' wins over the first 'a'
+        let positions = fuzzy_positions(&"ab".to_string(), &"xa_ab".to_string(), CaseMode::Sensitive);
+        assert!(positions.unwrap().1 == vec![(3, 5)]);
+        let positions = fuzzy_positions(&"é".to_string(), &"CAFÉ".to_string(), CaseMode::Insensitive);
+        assert!(positions.unwrap().1 == vec![(3, 5)]);
+    }
+
+    #[test]
+    fn test_fuzzy_repeated_letters_need_repeated_matches() {
+        assert!(fuzzy_positions(&"ll".to_string(), &"vlc".to_string(), CaseMode::Sensitive).is_none());
+        assert!(fuzzy_positions(&"ll".to_string(), &"shell".to_string(), CaseMode::Sensitive).unwrap().1 == vec![(3, 5)]);
+    }
+
     // #[test]
     // fn test_dmenu_match() {
     //     let match_results = dmenu_match(&"test".to_string(), &vec!["atest".to_string(), "test".to_string(), "testa".to_string(), "nomatch".to_string()]);
@@ -0,0 +1,1041 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use unicode_segmentation::UnicodeSegmentation;
+use bindings::Action;
+use history::History;
+use item::Item;
+use json::Json;
+use matches::{best_first, sort_scored, CaseMode, Matcher};
+use pool::MatchPool;
+
+/// Byte offset of the grapheme cluster boundary before `cursor`.
+fn prev_grapheme(text: &str, cursor: usize) -> usize {
+    text[..cursor].grapheme_indices(true).next_back().map(|(n, _)| n).unwrap_or(0)
+}
+
+/// Byte offset of the grapheme cluster boundary after `cursor`.
+fn next_grapheme(text: &str, cursor: usize) -> usize {
+    text[cursor..].graphemes(true).next().map(|g| cursor + g.len()).unwrap_or(cursor)
+}
+
+/// Byte offset of the start of the word before `cursor`, skipping trailing spaces.
+fn prev_word(text: &str, cursor: usize) -> usize {
+    let mut position = cursor;
+    while position > 0 && text[prev_grapheme(text, position)..position].trim().is_empty() {
+        position = prev_grapheme(text, position);
+    }
+    while position > 0 && !text[prev_grapheme(text, position)..position].trim().is_empty() {
+        position = prev_grapheme(text, position);
+    }
+    position
+}
+
+/// First match of each page when every row shows `lines` matches.
+pub fn vertical_page_starts(count: usize, lines: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    while starts[starts.len() - 1] + lines < count {
+        let next = starts[starts.len() - 1] + lines;
+        starts.push(next);
+    }
+    starts
+}
+
+/// First match of each page when the matches of `widths` are laid out in a
+/// row of `available` pixels, an item wider than the row gets its own page.
+///
+/// Stops at the page after the one holding the match at `upto`, so only the
+/// widths up to there are computed.
+pub fn horizontal_page_starts<I: Iterator<Item = i32>>(widths: I, available: i32, upto: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut x_pos = 0;
+    for (n, width) in widths.enumerate() {
+        if x_pos > 0 && x_pos + width > available {
+            starts.push(n);
+            x_pos = 0;
+            if n > upto {
+                break
+            }
+        }
+        x_pos += width;
+    }
+    starts
+}
+
+/// Sorts the scored matches best first, with the score of the items in
+/// `boosts` raised by the matcher's boost for their frecency. Equal ones
+/// keep their order.
+fn sort_boosted(scored: &mut Vec<(usize, f64)>, boosts: &HashMap<usize, f64>, matcher: &dyn Matcher) {
+    let key = |&(id, score): &(usize, f64)| score + boosts.get(&id).map(|&frecency| matcher.boost(frecency)).unwrap_or(0.0);
+    scored.sort_by(|x, y| best_first(key(x), key(y)));
+}
+
+/// Page that contains the match at `n`.
+fn page_of(starts: &[usize], n: usize) -> u32 {
+    starts.iter().rposition(|&start| start <= n).unwrap_or(0) as u32
+}
+
+/// How the menu was closed.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Outcome {
+    /// Ids of the accepted items, in the order they must be printed.
+    Selected(Vec<usize>),
+    /// The input text was accepted instead of an item.
+    Input(String),
+    Cancelled,
+}
+
+/// X selection to paste from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Selection {
+    Primary,
+    Clipboard,
+}
+
+/// What the front-end has to do after an action.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Step {
+    Continue,
+    /// Fetch the selection and hand its text to `MenuState::paste`.
+    Paste(Selection),
+    Close(Outcome),
+}
+
+/// What the state needs from the front-end that shows it.
+pub trait View {
+    /// First match of each page, at least up to the page after the one
+    /// holding the match at `upto`.
+    fn page_starts(&self, state: &MenuState, upto: usize) -> Vec<usize>;
+
+    /// Whether new input arrived that makes a running match useless.
+    fn interrupted(&self) -> bool {
+        false
+    }
+}
+
+/// Pages of a fixed number of rows.
+pub struct Rows(pub usize);
+
+impl View for Rows {
+    fn page_starts(&self, state: &MenuState, _upto: usize) -> Vec<usize> {
+        vertical_page_starts(state.matches.len(), self.0)
+    }
+}
+
+/// What an item has besides the text it is matched with.
+struct Details {
+    /// Length of the label at the start of the matched text.
+    label_length: usize,
+    value: Option<String>,
+    selectable: bool,
+    object: Option<Json>,
+}
+
+/// The menu without any display: the input text, the matches and the selection.
+pub struct MenuState {
+    pub text: String,
+    /// Byte offset of the cursor in `text`.
+    pub cursor: usize,
+    /// Text matched of each item: its label, followed by its keywords
+    /// when it has any.
+    pub items: Arc<Vec<String>>,
+    /// Details of the items that are more than a line of text.
+    details: Vec<Option<Box<Details>>>,
+    /// Ids of the matching items, an item id is its index in `items`.
+    pub matches: Vec<usize>,
+    /// Index of the selected match in `matches`.
+    pub selected: Option<usize>,
+    /// Ids of the marked items, in the order they were marked.
+    pub marked: Vec<usize>,
+    pub page: u32,
+    vertical: bool,
+    multi: bool,
+    case_mode: CaseMode,
+    matcher: Arc<dyn Matcher>,
+    pool: MatchPool,
+    /// Scored matches of `ranked_text` among the first `ranked_count` items.
+    ranked: Vec<(usize, f64)>,
+    ranked_text: String,
+    ranked_count: usize,
+    /// Whether the last match was interrupted, leaving `matches` behind `text`.
+    stale: bool,
+    /// Frecency of the history lines, and of the items that are one of them.
+    frecency: HashMap<String, f64>,
+    boosts: HashMap<usize, f64>,
+    /// History lines, most recent first, and the one shown in `text`.
+    history: Vec<String>,
+    history_pos: Option<usize>,
+}
+
+impl MenuState {
+    pub fn new(items: Vec<Item>, matcher: Box<dyn Matcher>, settings: &super::Settings) -> MenuState {
+        let mut state = MenuState {
+            text: String::new(),
+            cursor: 0,
+            items: Arc::new(vec![]),
+            details: vec![],
+            matches: vec![],
+            selected: None,
+            marked: vec![],
+            page: 0,
+            vertical: settings.lines > 0,
+            multi: settings.multi,
+            case_mode: settings.case_mode,
+            matcher: Arc::from(matcher),
+            pool: MatchPool::with_cpus(),
+            ranked: vec![],
+            ranked_text: String::new(),
+            ranked_count: 0,
+            stale: false,
+            frecency: HashMap::new(),
+            boosts: HashMap::new(),
+            history: vec![],
+            history_pos: None,
+        };
+        state.append(items);
+        state.update_matches(&|| false);
+        state
+    }
+
+    /// Id of the selected item, its index in `items`.
+    pub fn selected_item(&self) -> Option<usize> {
+        self.selected.map(|n| self.matches[n])
+    }
+
+    /// Raises the score of the items found in `history` by the matcher's
+    /// `boost` for their frecency, and lets Up and Down walk it.
+    pub fn set_history(&mut self, history: &History, now: u64) {
+        self.frecency = history.frecency(now);
+        self.history = history.recent();
+        self.history_pos = None;
+        self.boosts.clear();
+        self.boost_items(0);
+        self.ranked.clear();
+        self.ranked_text = self.text.clone();
+        self.ranked_count = 0;
+        self.selected = None;
+        self.update_matches(&|| false);
+    }
+
+    /// Text shown for the item `id`.
+    pub fn label(&self, id: usize) -> &str {
+        match self.details[id] {
+            Some(ref details) => &self.items[id][..details.label_length],
+            None => &self.items[id]
+        }
+    }
+
+    /// Text printed when the item `id` is accepted.
+    pub fn value(&self, id: usize) -> &str {
+        match self.details[id].as_ref().and_then(|details| details.value.as_ref()) {
+            Some(value) => value,
+            None => self.label(id)
+        }
+    }
+
+    /// The JSON object the item `id` was read from.
+    pub fn object(&self, id: usize) -> Option<&Json> {
+        self.details[id].as_ref().and_then(|details| details.object.as_ref())
+    }
+
+    fn selectable(&self, id: usize) -> bool {
+        self.details[id].as_ref().map(|details| details.selectable).unwrap_or(true)
+    }
+
+    fn append(&mut self, items: Vec<Item>) {
+        let start = self.items.len();
+        {
+            let texts = Arc::make_mut(&mut self.items);
+            for item in items {
+                if item.value.is_none() && item.keywords.is_none() && item.selectable && item.object.is_none() {
+                    texts.push(item.label);
+                    self.details.push(None);
+                    continue
+                }
+                let label_length = item.label.len();
+                let mut text = item.label;
+                if let Some(keywords) = item.keywords {
+                    text.push('\n');
+                    text.push_str(&keywords);
+                }
+                texts.push(text);
+                self.details.push(Some(Box::new(Details {
+                    label_length: label_length,
+                    value: item.value,
+                    selectable: item.selectable,
+                    object: item.object,
+                })));
+            }
+        }
+        self.boost_items(start);
+    }
+
+    /// Looks up the frecency of the items from `start` on.
+    fn boost_items(&mut self, start: usize) {
+        if self.frecency.is_empty() {
+            return
+        }
+        for id in start..self.items.len() {
+            if let Some(&frecency) = self.frecency.get(self.value(id)) {
+                self.boosts.insert(id, frecency);
+            }
+        }
+    }
+
+    /// Byte ranges of the label of the item `id` matched by `text`.
+    pub fn highlight(&self, id: usize) -> Vec<(usize, usize)> {
+        let length = self.label(id).len();
+        let spans = self.matcher.highlight(&self.text, &self.items[id], self.case_mode);
+        spans.into_iter().filter(|&(start, _)| start < length).map(|(start, end)| (start, end.min(length))).collect()
+    }
+
+    /// Whether `matches` are behind `text` because matching was interrupted.
+    pub fn stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Scores the items against `text`, reusing the previous scores when
+    /// only items were added and narrowing the previous matches when the
+    /// text only grew. Returns false when `interrupted` stopped it.
+    fn rank(&mut self, interrupted: &dyn Fn() -> bool) -> bool {
+        let count = self.items.len();
+        let same_text = self.text == self.ranked_text;
+        let candidates: Vec<usize> = if same_text {
+            (self.ranked_count..count).collect()
+        } else if self.matcher.narrows() && self.text.starts_with(&self.ranked_text) {
+            let mut candidates: Vec<usize> = self.ranked.iter().map(|&(id, _)| id).collect();
+            candidates.sort();
+            candidates.extend(self.ranked_count..count);
+            candidates
+        } else {
+            (0..count).collect()
+        };
+
+        let mut ranked = match self.pool.rank(&self.matcher, &self.text, &self.items, candidates, self.case_mode, interrupted) {
+            Some(ranked) => ranked,
+            None => {
+                self.stale = true;
+                return false
+            }
+        };
+        if same_text {
+            // Both are sorted and the new ids come last, so ties keep the item order
+            self.ranked.extend(ranked);
+            if self.boosts.is_empty() {
+                sort_scored(&mut self.ranked);
+            } else {
+                sort_boosted(&mut self.ranked, &self.boosts, &*self.matcher);
+            }
+        } else {
+            if !self.boosts.is_empty() {
+                sort_boosted(&mut ranked, &self.boosts, &*self.matcher);
+            }
+            self.ranked = ranked;
+            self.ranked_text = self.text.clone();
+        }
+        self.ranked_count = count;
+        self.stale = false;
+        true
+    }
+
+    /// Re-runs the matcher and keeps the selected item when it still
+    /// matches, otherwise selects the first match.
+    fn update_matches(&mut self, interrupted: &dyn Fn() -> bool) -> bool {
+        let selected_item = self.selected_item();
+        if !self.rank(interrupted) {
+            return false
+        }
+        self.matches = self.ranked.iter().map(|&(id, _)| id).collect();
+        self.page = 0;
+        self.selected = match selected_item.and_then(|id| self.matches.iter().position(|&m| m == id)) {
+            Some(n) => Some(n),
+            None => if self.matches.len() > 0 { Some(0) } else { None }
+        };
+        true
+    }
+
+    /// Updates the matches for the view, which may interrupt it.
+    pub fn refresh(&mut self, view: &dyn View) {
+        if self.update_matches(&|| view.interrupted()) {
+            self.sync_page(view);
+        }
+    }
+
+    /// Brings stale matches up to date without letting anything interrupt it,
+    /// before acting on the selection.
+    fn catch_up(&mut self, view: &dyn View) {
+        if self.stale && self.update_matches(&|| false) {
+            self.sync_page(view);
+        }
+    }
+
+    fn select_first(&mut self) {
+        if self.matches.len() > 0 {
+            self.selected = Some(0);
+        }
+    }
+
+    fn select_last(&mut self) {
+        if self.matches.len() > 0 {
+            self.selected = Some(self.matches.len() - 1);
+        }
+    }
+
+    fn select_prev(&mut self) {
+        if let Some(n) = self.selected {
+            if n > 0 {
+                self.selected = Some(n - 1);
+            }
+        }
+    }
+
+    fn select_next(&mut self) {
+        if let Some(n) = self.selected {
+            if n + 1 < self.matches.len() {
+                self.selected = Some(n + 1);
+            }
+        }
+    }
+
+    /// Moves to the page that contains the selected match.
+    fn sync_page(&mut self, view: &dyn View) {
+        if let Some(n) = self.selected {
+            self.page = page_of(&view.page_starts(self, n), n);
+        }
+    }
+
+    /// Turns the page and selects its first match.
+    fn turn_page(&mut self, view: &dyn View, forward: bool) {
+        let starts = view.page_starts(self, self.selected.unwrap_or(0));
+        let page = self.page as usize;
+        if forward && page + 1 < starts.len() {
+            self.page += 1;
+        } else if !forward && page > 0 {
+            self.page -= 1;
+        } else {
+            return
+        }
+        if self.matches.len() > 0 {
+            self.selected = Some(starts[self.page as usize]);
+        }
+    }
+
+    /// Inserts typed or pasted text at the cursor, control characters are dropped.
+    pub fn insert(&mut self, text: &str, view: &dyn View) {
+        let text: String = text.chars().filter(|c| !c.is_control()).collect();
+        if text.is_empty() {
+            return
+        }
+        self.text.insert_str(self.cursor, &text);
+        self.cursor += text.len();
+        self.refresh(view);
+    }
+
+    /// Appends items that arrived after the menu was shown.
+    pub fn add_items(&mut self, items: Vec<Item>, view: &dyn View) {
+        if items.is_empty() {
+            return
+        }
+        self.append(items);
+        self.refresh(view);
+    }
+
+    /// Inserts the first line of a pasted selection.
+    pub fn paste(&mut self, text: &str, view: &dyn View) {
+        self.insert(text.lines().next().unwrap_or(""), view);
+    }
+
+    /// Position in `history` of the line shown in `text`, if it was put there by Up or Down.
+    fn walking_history(&self) -> Option<usize> {
+        self.history_pos.filter(|&n| self.history[n] == self.text)
+    }
+
+    /// Shows the history line at `n`, or an empty query, and selects its first match.
+    fn show_history(&mut self, n: Option<usize>) {
+        self.history_pos = n;
+        self.selected = None;
+        self.text = n.map(|n| self.history[n].clone()).unwrap_or_default();
+        self.cursor = self.text.len();
+    }
+
+    /// Closes with the marked items, or the selected one, or the input
+    /// text when nothing matches. Items that are not selectable stay open.
+    fn accept(&self) -> Option<Outcome> {
+        if self.marked.len() > 0 {
+            return Some(Outcome::Selected(self.marked.clone()))
+        }
+        match self.selected_item() {
+            Some(id) if !self.selectable(id) => None,
+            Some(id) => Some(Outcome::Selected(vec![id])),
+            None => Some(Outcome::Input(self.text.clone()))
+        }
+    }
+
+    /// Selects the match at `n`, like a click on it does, and runs `action` on it.
+    ///
+    /// When the matches shown are stale they are updated instead, the click
+    /// landed on a match that may no longer be there.
+    pub fn click(&mut self, n: usize, action: Action, view: &dyn View) -> Step {
+        if self.stale {
+            self.catch_up(view);
+            return Step::Continue
+        }
+        if n < self.matches.len() {
+            self.selected = Some(n);
+        }
+        self.handle(action, view)
+    }
+
+    pub fn handle(&mut self, action: Action, view: &dyn View) -> Step {
+        match action {
+            Action::Accept | Action::Complete | Action::ToggleMark => self.catch_up(view),
+            _ => ()
+        }
+        let old_text = self.text.clone();
+
+        match action {
+            Action::CursorLeft => self.cursor = prev_grapheme(&self.text, self.cursor),
+            Action::CursorRight => self.cursor = next_grapheme(&self.text, self.cursor),
+            Action::CursorStart => self.cursor = 0,
+            Action::CursorEnd => self.cursor = self.text.len(),
+            Action::DeleteToEnd => self.text.truncate(self.cursor),
+            Action::DeleteToStart => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+            },
+            Action::DeleteWord => {
+                let start = prev_word(&self.text, self.cursor);
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+            },
+            Action::PastePrimary => return Step::Paste(Selection::Primary),
+            Action::PasteClipboard => return Step::Paste(Selection::Clipboard),
+            Action::DeleteRight => {
+                if self.cursor < self.text.len() {
+                    let end = next_grapheme(&self.text, self.cursor);
+                    self.text.drain(self.cursor..end);
+                }
+            },
+            Action::DeleteLeft => {
+                if self.cursor > 0 {
+                    let start = prev_grapheme(&self.text, self.cursor);
+                    self.text.drain(start..self.cursor);
+                    self.cursor = start;
+                }
+            },
+            Action::End => {
+                if self.cursor < self.text.len() {
+                    self.cursor = self.text.len();
+                } else {
+                    self.select_last();
+                }
+            },
+            Action::SelectLast => self.select_last(),
+            Action::Cancel => return Step::Close(Outcome::Cancelled),
+            Action::Home => {
+                if self.selected == Some(0) {
+                    self.cursor = 0;
+                } else {
+                    self.select_first();
+                }
+            },
+            Action::SelectFirst => self.select_first(),
+            Action::Left => {
+                if self.vertical || self.selected.unwrap_or(0) == 0 {
+                    self.cursor = prev_grapheme(&self.text, self.cursor);
+                } else {
+                    self.select_prev();
+                }
+            },
+            Action::Up => {
+                match self.walking_history() {
+                    Some(n) => if n + 1 < self.history.len() { self.show_history(Some(n + 1)) },
+                    // Starts walking only when there is no match above to select
+                    None if self.text.is_empty() && self.history.len() > 0 && self.selected.unwrap_or(0) == 0 =>
+                        self.show_history(Some(0)),
+                    None => self.select_prev()
+                }
+            },
+            Action::Down => {
+                match self.walking_history() {
+                    Some(0) => self.show_history(None),
+                    Some(n) => self.show_history(Some(n - 1)),
+                    None => self.select_next()
+                }
+            },
+            Action::SelectPrev => self.select_prev(),
+            Action::PageNext => self.turn_page(view, true),
+            Action::PagePrev => self.turn_page(view, false),
+            Action::ToggleMark => {
+                if self.multi {
+                    if let Some(id) = self.selected_item().filter(|&id| self.selectable(id)) {
+                        match self.marked.iter().position(|&marked| marked == id) {
+                            Some(n) => { self.marked.remove(n); },
+                            None => self.marked.push(id)
+                        }
+                    }
+                }
+            },
+            Action::Accept => if let Some(outcome) = self.accept() {
+                return Step::Close(outcome)
+            },
+            Action::AcceptInput => return Step::Close(Outcome::Input(self.text.clone())),
+            Action::Right => {
+                if self.cursor < self.text.len() {
+                    self.cursor = next_grapheme(&self.text, self.cursor);
+                } else {
+                    self.select_next();
+                }
+            },
+            Action::SelectNext => self.select_next(),
+            Action::Complete => {
+                if let Some(id) = self.selected_item() {
+                    self.text = self.label(id).to_string();
+                    self.cursor = self.text.len();
+                }
+            },
+        }
+        if old_text != self.text {
+            self.refresh(view);
+        } else {
+            self.sync_page(view);
+        }
+        Step::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prev_grapheme, next_grapheme, prev_word, vertical_page_starts, horizontal_page_starts, page_of};
+    use super::{MenuState, Outcome, Rows, Selection, Step, View};
+    use bindings::{Action, Bindings};
+    use fields::{parse_field_list, Fields};
+    use item::{Format, Item};
+    use json::Json;
+    use xmini::{Geometry, Monitor};
+    use history::History;
+    use matches::{filter, CaseMode, DmenuMatcher, FuzzyMatcher, SimpleMatcher};
+
+    fn settings(lines: u32, multi: bool) -> super::super::Settings {
+        super::super::Settings {
+            topbar: true,
+            monitor: Monitor::Focus,
+            geometry: Geometry { x: 0, y: 0, width: None, center: false, border: 0 },
+            embed: None,
+            grab_pointer: false,
+            case_mode: CaseMode::Sensitive,
+            lines: lines,
+            prompt: String::new(),
+            fonts: vec![],
+            normbgcolor: String::new(),
+            normfgcolor: String::new(),
+            selbgcolor: String::new(),
+            selfgcolor: String::new(),
+            bordercolor: String::new(),
+            hlbgcolor: String::new(),
+            hlfgcolor: String::new(),
+            selhlbgcolor: String::new(),
+            selhlfgcolor: String::new(),
+            cache_file: "-".to_string(),
+            history_file: None,
+            format: Format::Lines(None),
+            matcher: "simple".to_string(),
+            multi: multi,
+            fast: false,
+            bindings: Bindings::new(),
+        }
+    }
+
+    fn to_items(texts: Vec<String>) -> Vec<Item> {
+        texts.into_iter().map(Item::from).collect()
+    }
+
+    fn state(texts: &[&str], lines: u32) -> MenuState {
+        let texts = texts.iter().map(|text| text.to_string()).collect();
+        MenuState::new(to_items(texts), Box::new(SimpleMatcher), &settings(lines, false))
+    }
+
+    fn type_text(state: &mut MenuState, text: &str) {
+        state.insert(text, &Rows(3));
+    }
+
+    #[test]
+    fn test_grapheme_movement_over_multibyte_text() {
+        let text = "añe\u{301}漢";
+        assert!(next_grapheme(text, 0) == 1);
+        assert!(next_grapheme(text, 1) == 3);
+        // 'e' with a combining acute accent is a single cluster
+        assert!(next_grapheme(text, 3) == 6);
+        assert!(next_grapheme(text, 6) == 9);
+        assert!(next_grapheme(text, 9) == 9);
+        assert!(prev_grapheme(text, 9) == 6);
+        assert!(prev_grapheme(text, 6) == 3);
+        assert!(prev_grapheme(text, 3) == 1);
+        assert!(prev_grapheme(text, 0) == 0);
+    }
+
+    #[test]
+    fn test_prev_word() {
+        let text = "ls ñandú  ";
+        assert!(prev_word(text, text.len()) == 3);
+        assert!(prev_word(text, 3) == 0);
+        assert!(prev_word(text, 0) == 0);
+    }
+
+    #[test]
+    fn test_navigation_follows_the_ranking() {
+        let mut state = state(&["atest", "test", "testa"], 0);
+        type_text(&mut state, "test");
+        assert!(state.matches == vec![1, 2, 0]);
+        // "atest" was selected and still matches
+        assert!(state.selected == Some(2));
+        state.handle(Action::SelectFirst, &Rows(3));
+        assert!(state.selected_item() == Some(1));
+        state.handle(Action::SelectNext, &Rows(3));
+        assert!(state.selected_item() == Some(2));
+        state.handle(Action::SelectNext, &Rows(3));
+        state.handle(Action::SelectNext, &Rows(3));
+        assert!(state.selected_item() == Some(0));
+        state.handle(Action::SelectPrev, &Rows(3));
+        assert!(state.selected_item() == Some(2));
+        state.handle(Action::SelectFirst, &Rows(3));
+        state.handle(Action::SelectPrev, &Rows(3));
+        assert!(state.selected == Some(0));
+    }
+
+    #[test]
+    fn test_duplicates_are_selected_separately() {
+        let mut state = state(&["dup", "dup", "other"], 0);
+        state.handle(Action::SelectNext, &Rows(3));
+        assert!(state.selected == Some(1));
+        assert!(state.selected_item() == Some(1));
+        state.handle(Action::SelectLast, &Rows(3));
+        assert!(state.selected_item() == Some(2));
+    }
+
+    #[test]
+    fn test_selection_survives_refiltering() {
+        let mut state = state(&["firefox", "fish", "vim", "fizz"], 0);
+        state.handle(Action::SelectLast, &Rows(3));
+        assert!(state.selected_item() == Some(3));
+        type_text(&mut state, "fi");
+        assert!(state.matches == vec![0, 1, 3]);
+        assert!(state.selected == Some(2));
+        state.handle(Action::DeleteToStart, &Rows(3));
+        type_text(&mut state, "vi");
+        assert!(state.selected_item() == Some(2));
+        type_text(&mut state, "nothing");
+        assert!(state.selected == None);
+        state.handle(Action::SelectNext, &Rows(3));
+        state.handle(Action::SelectLast, &Rows(3));
+        assert!(state.selected == None);
+    }
+
+    #[test]
+    fn test_paging() {
+        let mut state = state(&["a", "b", "c", "d", "e"], 2);
+        state.handle(Action::PageNext, &Rows(2));
+        state.handle(Action::PageNext, &Rows(2));
+        assert!(state.page == 2 && state.selected == Some(4));
+        state.handle(Action::PageNext, &Rows(2));
+        assert!(state.page == 2);
+        state.handle(Action::SelectPrev, &Rows(2));
+        assert!(state.page == 1);
+        state.handle(Action::PagePrev, &Rows(2));
+        assert!(state.page == 0 && state.selected == Some(0));
+    }
+
+    #[test]
+    fn test_page_starts() {
+        assert!(vertical_page_starts(0, 3) == vec![0]);
+        assert!(vertical_page_starts(3, 3) == vec![0]);
+        let widths = [30, 30, 30, 80, 10];
+        assert!(horizontal_page_starts(widths.iter().cloned(), 70, 4) == vec![0, 2, 3, 4]);
+        assert!(horizontal_page_starts(widths.iter().cloned(), 70, 1) == vec![0, 2]);
+        assert!(page_of(&[0, 2, 3, 4], 2) == 1);
+        assert!(page_of(&[0, 2, 3, 4], 1) == 0);
+        assert!(page_of(&[0, 2, 3, 4], 9) == 3);
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut state = state(&[], 0);
+        type_text(&mut state, "ls ñandú");
+        state.handle(Action::CursorLeft, &Rows(3));
+        state.handle(Action::DeleteLeft, &Rows(3));
+        assert!(state.text == "ls ñanú" && state.cursor == 7);
+        state.handle(Action::DeleteRight, &Rows(3));
+        assert!(state.text == "ls ñan");
+        state.handle(Action::CursorStart, &Rows(3));
+        state.handle(Action::DeleteRight, &Rows(3));
+        assert!(state.text == "s ñan" && state.cursor == 0);
+        state.handle(Action::CursorEnd, &Rows(3));
+        state.handle(Action::DeleteWord, &Rows(3));
+        assert!(state.text == "s " && state.cursor == 2);
+        state.handle(Action::CursorLeft, &Rows(3));
+        state.handle(Action::DeleteToEnd, &Rows(3));
+        assert!(state.text == "s");
+        type_text(&mut state, "\u{7}x\n");
+        assert!(state.text == "sx" && state.cursor == 2);
+        state.handle(Action::DeleteToStart, &Rows(3));
+        assert!(state.text == "" && state.cursor == 0);
+    }
+
+    #[test]
+    fn test_left_and_right_move_the_selection_at_the_ends() {
+        let mut state = state(&["one", "two"], 0);
+        type_text(&mut state, "o");
+        state.handle(Action::Right, &Rows(3));
+        assert!(state.selected == Some(1) && state.cursor == 1);
+        state.handle(Action::Left, &Rows(3));
+        assert!(state.selected == Some(0) && state.cursor == 1);
+        state.handle(Action::Left, &Rows(3));
+        assert!(state.cursor == 0);
+        state.handle(Action::Right, &Rows(3));
+        assert!(state.cursor == 1 && state.selected == Some(0));
+    }
+
+    #[test]
+    fn test_home_and_end() {
+        let mut state = state(&["one", "two", "three"], 0);
+        type_text(&mut state, "o");
+        state.handle(Action::End, &Rows(3));
+        assert!(state.selected == Some(1));
+        state.handle(Action::Home, &Rows(3));
+        assert!(state.selected == Some(0) && state.cursor == 1);
+        state.handle(Action::Home, &Rows(3));
+        assert!(state.cursor == 0);
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut state = state(&["firefox", "fish"], 0);
+        type_text(&mut state, "fi");
+        state.handle(Action::SelectNext, &Rows(3));
+        state.handle(Action::Complete, &Rows(3));
+        assert!(state.text == "fish" && state.cursor == 4);
+        assert!(state.matches == vec![1]);
+    }
+
+    #[test]
+    fn test_accept() {
+        let mut state = state(&["firefox", "fish"], 0);
+        assert!(state.handle(Action::Accept, &Rows(3)) == Step::Close(Outcome::Selected(vec![0])));
+        type_text(&mut state, "vim");
+        assert!(state.handle(Action::Accept, &Rows(3)) == Step::Close(Outcome::Input("vim".to_string())));
+        assert!(state.handle(Action::AcceptInput, &Rows(3)) == Step::Close(Outcome::Input("vim".to_string())));
+        assert!(state.handle(Action::Cancel, &Rows(3)) == Step::Close(Outcome::Cancelled));
+    }
+
+    #[test]
+    fn test_marks_are_printed_in_mark_order() {
+        let items = to_items(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        let mut state = MenuState::new(items, Box::new(SimpleMatcher), &settings(0, true));
+        state.handle(Action::SelectLast, &Rows(3));
+        state.handle(Action::ToggleMark, &Rows(3));
+        state.handle(Action::SelectFirst, &Rows(3));
+        state.handle(Action::ToggleMark, &Rows(3));
+        state.handle(Action::SelectNext, &Rows(3));
+        state.handle(Action::ToggleMark, &Rows(3));
+        state.handle(Action::ToggleMark, &Rows(3));
+        assert!(state.marked == vec![2, 0]);
+        let outcome = Outcome::Selected(vec![2, 0]);
+        assert!(state.handle(Action::Accept, &Rows(3)) == Step::Close(outcome));
+    }
+
+    #[test]
+    fn test_clicks() {
+        let items = to_items(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        let mut state = MenuState::new(items, Box::new(SimpleMatcher), &settings(0, true));
+        assert!(state.click(2, Action::ToggleMark, &Rows(3)) == Step::Continue);
+        assert!(state.selected == Some(2) && state.marked == vec![2]);
+        state.marked.clear();
+        assert!(state.click(1, Action::Accept, &Rows(3)) == Step::Close(Outcome::Selected(vec![1])));
+        // A click past the matches keeps the selection
+        assert!(state.click(5, Action::Accept, &Rows(3)) == Step::Close(Outcome::Selected(vec![1])));
+    }
+
+    #[test]
+    fn test_items_arriving_keep_the_selection() {
+        let mut state = state(&[], 0);
+        assert!(state.selected == None);
+        state.add_items(to_items(vec!["vim".to_string(), "firefox".to_string()]), &Rows(1));
+        assert!(state.selected_item() == Some(0));
+        state.handle(Action::SelectNext, &Rows(1));
+        state.add_items(to_items(vec!["emacs".to_string()]), &Rows(1));
+        assert!(state.matches == vec![0, 1, 2]);
+        assert!(state.selected_item() == Some(1) && state.page == 1);
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut state = state(&["hello"], 0);
+        assert!(state.handle(Action::PasteClipboard, &Rows(3)) == Step::Paste(Selection::Clipboard));
+        state.paste("he\tllo\nworld", &Rows(3));
+        assert!(state.text == "hello" && state.matches == vec![0]);
+    }
+
+    #[test]
+    fn test_history_boosts_by_frecency() {
+        let mut state = state(&["gimp", "firefox", "gedit", "go"], 0);
+        let history = History::parse("1\t90\tgo\n5\t100\tgedit\n");
+        state.set_history(&history, 100);
+        assert!(state.matches == vec![2, 3, 0, 1]);
+        type_text(&mut state, "g");
+        assert!(state.matches == vec![2, 3, 0]);
+        state.add_items(to_items(vec!["gedit".to_string()]), &Rows(3));
+        assert!(state.matches == vec![2, 4, 3, 0]);
+        // A better score still wins over frecency
+        state.handle(Action::DeleteLeft, &Rows(3));
+        type_text(&mut state, "i");
+        assert!(state.matches == vec![0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn test_frecent_items_beat_slightly_better_matches() {
+        let history = History::parse("2\t100\tgedit\n");
+        let mut state = state(&["emacs", "gedit"], 0);
+        state.set_history(&history, 100);
+        type_text(&mut state, "e");
+        assert!(state.matches == vec![1, 0]);
+
+        let items = to_items(vec!["emacs".to_string(), "gedit".to_string()]);
+        let mut state = MenuState::new(items, Box::new(FuzzyMatcher), &settings(0, false));
+        state.set_history(&history, 100);
+        type_text(&mut state, "e");
+        assert!(state.matches == vec![1, 0]);
+
+        // The dmenu matcher keeps a prefix match above a frecent substring match
+        let items = to_items(vec!["emacs".to_string(), "gedit".to_string()]);
+        let mut state = MenuState::new(items, Box::new(DmenuMatcher), &settings(0, false));
+        state.set_history(&history, 100);
+        type_text(&mut state, "e");
+        assert!(state.matches == vec![0, 1]);
+    }
+
+    #[test]
+    fn test_up_and_down_walk_the_history() {
+        let mut state = state(&["one", "two", "three"], 3);
+        state.set_history(&History::parse("1\t100\ttwo\n1\t200\tthree\n"), 200);
+        state.handle(Action::Down, &Rows(3));
+        assert!(state.text == "" && state.selected == Some(1));
+        // Up selects the previous match until there is none
+        state.handle(Action::Up, &Rows(3));
+        assert!(state.text == "" && state.selected == Some(0));
+        state.handle(Action::Up, &Rows(3));
+        assert!(state.text == "three" && state.cursor == 5);
+        state.handle(Action::Up, &Rows(3));
+        assert!(state.text == "two");
+        state.handle(Action::Up, &Rows(3));
+        assert!(state.text == "two");
+        state.handle(Action::Down, &Rows(3));
+        assert!(state.text == "three");
+        state.handle(Action::Down, &Rows(3));
+        assert!(state.text == "");
+        // Editing the text stops the walk
+        state.handle(Action::Up, &Rows(3));
+        state.handle(Action::DeleteLeft, &Rows(3));
+        state.handle(Action::Up, &Rows(3));
+        assert!(state.text == "thre");
+    }
+
+    #[test]
+    fn test_fields_split_the_shown_and_printed_text() {
+        let fields = Fields::new("\t".to_string(), Some(parse_field_list("2").unwrap()), Some(parse_field_list("1").unwrap()));
+        let format = Format::Lines(Some(fields));
+        let lines = vec!["firefox\tFirefox — web browser", "gimp\tGIMP — image editor"];
+        let items = lines.iter().map(|line| Item::parse(line.to_string(), &format).unwrap()).collect();
+        let mut state = MenuState::new(items, Box::new(SimpleMatcher), &settings(0, false));
+        assert!(state.label(0) == "Firefox — web browser" && state.label(1) == "GIMP — image editor");
+        type_text(&mut state, "image");
+        assert!(state.handle(Action::Accept, &Rows(3)) == Step::Close(Outcome::Selected(vec![1])));
+        assert!(state.value(1) == "gimp");
+        // Frecency follows the printed text
+        state.set_history(&History::parse("1\t100\tgimp\n"), 100);
+        state.handle(Action::DeleteToStart, &Rows(3));
+        assert!(state.matches == vec![1, 0]);
+    }
+
+    #[test]
+    fn test_jsonl_items() {
+        let lines = vec![
+            r#"{"label": "Browsers", "selectable": false}"#,
+            r#"{"label": "Firefox", "value": "firefox", "meta": "web www"}"#,
+            r#"{"label": "Chromium", "meta": ["web"], "group": "browsers"}"#,
+        ];
+        let items = lines.iter().map(|line| Item::parse(line.to_string(), &Format::Jsonl).unwrap()).collect();
+        let mut state = MenuState::new(items, Box::new(SimpleMatcher), &settings(0, true));
+        // Not selectable items can neither be accepted nor marked
+        assert!(state.handle(Action::Accept, &Rows(3)) == Step::Continue);
+        state.handle(Action::ToggleMark, &Rows(3));
+        assert!(state.marked.len() == 0);
+
+        type_text(&mut state, "web");
+        assert!(state.matches == vec![1, 2] && state.label(1) == "Firefox");
+        assert!(state.highlight(1) == vec![]);
+        state.handle(Action::Complete, &Rows(3));
+        assert!(state.text == "Firefox");
+        assert!(state.handle(Action::Accept, &Rows(3)) == Step::Close(Outcome::Selected(vec![1])));
+        assert!(state.value(1) == "firefox" && state.value(2) == "Chromium");
+        assert!(state.object(2).unwrap().get("group") == Some(&Json::String("browsers".to_string())));
+    }
+
+    #[test]
+    fn test_highlight_follows_the_text() {
+        let mut state = state(&["a-test-b", "other"], 0);
+        assert!(state.highlight(0) == vec![]);
+        type_text(&mut state, "test");
+        assert!(state.highlight(state.matches[0]) == vec![(2, 6)]);
+    }
+
+    #[test]
+    fn test_narrowed_matches_equal_a_full_match() {
+        let items: Vec<String> = (0..20000).map(|n| format!("{} file-{}", n % 7, n)).collect();
+        let mut state = MenuState::new(to_items(items.clone()), Box::new(DmenuMatcher), &settings(0, false));
+        for c in "1 fi".chars() {
+            type_text(&mut state, &c.to_string());
+            assert!(state.matches == filter(&DmenuMatcher, &state.text, &items, CaseMode::Sensitive));
+        }
+        state.add_items(to_items(vec!["1 fi".to_string(), "nope".to_string()]), &Rows(3));
+        let mut all_items = items.clone();
+        all_items.extend(vec!["1 fi".to_string(), "nope".to_string()]);
+        assert!(state.matches == filter(&DmenuMatcher, &state.text, &all_items, CaseMode::Sensitive));
+        assert!(state.matches.contains(&20000) && !state.matches.contains(&20001));
+    }
+
+    #[test]
+    fn test_interrupted_matching_leaves_the_matches_stale() {
+        struct Busy;
+        impl View for Busy {
+            fn page_starts(&self, state: &MenuState, _upto: usize) -> Vec<usize> { Rows(3).page_starts(state, 0) }
+            fn interrupted(&self) -> bool { true }
+        }
+
+        let items = to_items((0..50000).map(|n| format!("item-{}", n)).collect());
+        let mut state = MenuState::new(items, Box::new(SimpleMatcher), &settings(0, false));
+        state.insert("x", &Busy);
+        assert!(state.stale() && state.matches.len() == 50000);
+        state.refresh(&Rows(3));
+        assert!(!state.stale() && state.matches.len() == 0 && state.selected == None);
+    }
+
+    #[test]
+    fn test_stale_matches_are_updated_before_accepting() {
+        struct Busy;
+        impl View for Busy {
+            fn page_starts(&self, state: &MenuState, _upto: usize) -> Vec<usize> { Rows(3).page_starts(state, 0) }
+            fn interrupted(&self) -> bool { true }
+        }
+
+        let mut state = state(&["vim", "emacs", "nano"], 3);
+        state.insert("e", &Busy);
+        assert!(state.stale() && state.selected_item() == Some(0));
+        assert!(state.handle(Action::Accept, &Busy) == Step::Close(Outcome::Selected(vec![1])));
+
+        state.insert("z", &Busy);
+        assert!(state.stale());
+        assert!(state.click(0, Action::Accept, &Busy) == Step::Continue);
+        assert!(!state.stale() && state.matches.len() == 0);
+        assert!(state.handle(Action::Accept, &Busy) == Step::Close(Outcome::Input("ez".to_string())));
+    }
+}
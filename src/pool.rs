@@ -0,0 +1,179 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use libc::{sysconf, _SC_NPROCESSORS_ONLN};
+
+use matches::{score_items, sort_scored, CaseMode, Matcher};
+
+/// Fewer candidates than this are scored on the calling thread.
+const PARALLEL_THRESHOLD: usize = 8192;
+
+/// Candidates scored between two checks of the stop flag.
+const STOP_CHECK: usize = 1024;
+
+/// How often `interrupted` is asked while waiting for the workers, in milliseconds.
+const INTERRUPT_POLL: u64 = 5;
+
+/// The scores of a part of the candidates, None when interrupted.
+type PartScores = (usize, Option<Vec<(usize, f64)>>);
+
+struct Task {
+    matcher: Arc<dyn Matcher>,
+    text: String,
+    case: CaseMode,
+    items: Arc<Vec<String>>,
+    candidates: Vec<usize>,
+    part: usize,
+    stop: Arc<AtomicBool>,
+    results: Sender<PartScores>,
+}
+
+fn work(tasks: Arc<Mutex<Receiver<Task>>>) {
+    loop {
+        let task = tasks.lock().unwrap().recv();
+        let Task { matcher, text, case, items, candidates, part, stop, results } = match task {
+            Ok(task) => task,
+            Err(_) => return
+        };
+
+        let mut scored = Some(vec![]);
+        for chunk in candidates.chunks(STOP_CHECK) {
+            if stop.load(Ordering::Relaxed) {
+                scored = None;
+                break
+            }
+            scored.as_mut().unwrap().extend(score_items(&*matcher, &text, &items, chunk, case));
+        }
+        // Release the items before answering, the caller may want to grow them
+        drop(items);
+        let _ = results.send((part, scored));
+    }
+}
+
+/// Threads that score the items in parallel.
+pub struct MatchPool {
+    tasks: Sender<Task>,
+    threads: usize,
+}
+
+impl MatchPool {
+    pub fn new(threads: usize) -> MatchPool {
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..threads {
+            let receiver = receiver.clone();
+            thread::spawn(move || work(receiver));
+        }
+        MatchPool { tasks: sender, threads: threads }
+    }
+
+    /// Creates a pool with a thread per online CPU, or a single one when
+    /// they cannot be counted.
+    pub fn with_cpus() -> MatchPool {
+        let cpus = unsafe { sysconf(_SC_NPROCESSORS_ONLN) };
+        MatchPool::new(if cpus > 0 { cpus as usize } else { 1 })
+    }
+
+    /// Scores the `candidates` of `items` and sorts the matches best first,
+    /// equal scores keep the order of `candidates`.
+    ///
+    /// `interrupted` is polled while scoring, the work stops and `None` is
+    /// returned as soon as it answers true.
+    pub fn rank(&self, matcher: &Arc<dyn Matcher>, text: &String, items: &Arc<Vec<String>>, candidates: Vec<usize>,
+                case: CaseMode, interrupted: &dyn Fn() -> bool) -> Option<Vec<(usize, f64)>> {
+        if candidates.len() < PARALLEL_THRESHOLD || self.threads < 2 {
+            let mut scored = vec![];
+            for chunk in candidates.chunks(STOP_CHECK) {
+                if interrupted() {
+                    return None
+                }
+                scored.extend(score_items(&**matcher, text, items, chunk, case));
+            }
+            sort_scored(&mut scored);
+            return Some(scored)
+        }
+
+        let (sender, receiver) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let part_size = candidates.len().div_ceil(self.threads * 4);
+        let mut parts = 0;
+        for chunk in candidates.chunks(part_size) {
+            let task = Task {
+                matcher: matcher.clone(),
+                text: text.clone(),
+                case: case,
+                items: items.clone(),
+                candidates: chunk.to_vec(),
+                part: parts,
+                stop: stop.clone(),
+                results: sender.clone(),
+            };
+            if self.tasks.send(task).is_err() {
+                return None
+            }
+            parts += 1;
+        }
+
+        let mut results: Vec<Option<Vec<(usize, f64)>>> = (0..parts).map(|_| None).collect();
+        let mut received = 0;
+        let mut stopped = false;
+        while received < parts {
+            if !stopped && interrupted() {
+                stop.store(true, Ordering::Relaxed);
+                stopped = true;
+            }
+            match receiver.recv_timeout(Duration::from_millis(INTERRUPT_POLL)) {
+                Ok((part, scored)) => {
+                    stopped |= scored.is_none();
+                    results[part] = scored;
+                    received += 1;
+                },
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return None
+            }
+        }
+        if stopped {
+            return None
+        }
+
+        let mut scored = vec![];
+        for part in results {
+            scored.extend(part.unwrap());
+        }
+        sort_scored(&mut scored);
+        Some(scored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use matches::{filter, CaseMode, Matcher, SimpleMatcher};
+    use super::MatchPool;
+
+    fn items(count: usize) -> Arc<Vec<String>> {
+        Arc::new((0..count).map(|n| format!("item-{}", n)).collect())
+    }
+
+    #[test]
+    fn test_parallel_ranking_matches_the_sequential_one() {
+        let items = items(50000);
+        let matcher: Arc<dyn Matcher> = Arc::new(SimpleMatcher);
+        let text = "7".to_string();
+        let scored = MatchPool::new(4).rank(&matcher, &text, &items, (0..items.len()).collect(), CaseMode::Sensitive, &|| false).unwrap();
+        let ranked: Vec<usize> = scored.iter().map(|&(n, _)| n).collect();
+        assert!(ranked == filter(&*matcher, &text, &items, CaseMode::Sensitive));
+    }
+
+    #[test]
+    fn test_interrupted_ranking_stops() {
+        let items = items(50000);
+        let matcher: Arc<dyn Matcher> = Arc::new(SimpleMatcher);
+        let result = MatchPool::new(2).rank(&matcher, &"x".to_string(), &items, (0..items.len()).collect(), CaseMode::Sensitive, &|| true);
+        assert!(result.is_none());
+        assert!(Arc::strong_count(&items) == 1);
+    }
+}
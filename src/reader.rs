@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, Write};
+use std::io::BufReader;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use error::RumenuError;
+use item::{Format, Item};
+
+enum Message {
+    Item(Item),
+    Done,
+    Failed(RumenuError),
+}
+
+/// Reads the items on a thread so the menu can show them while they arrive.
+pub struct ItemReader {
+    receiver: Receiver<Message>,
+    /// Items received by `wait` and not taken yet.
+    pending: Vec<Item>,
+    done: bool,
+}
+
+fn send_items<R: BufRead>(input: R, source: &str, format: &Format, sender: &Sender<Message>) {
+    for (n, line) in input.lines().enumerate() {
+        match line {
+            Ok(ref line) if *format == Format::Jsonl && line.trim().is_empty() => (),
+            Ok(line) => {
+                // A malformed line loses only its item
+                let item = match Item::parse(line, format) {
+                    Ok(item) => item,
+                    Err(e) => {
+                        writeln!(io::stderr(), "rumenu: {}: line {}: {}, skipped", source, n + 1, e).unwrap();
+                        continue
+                    }
+                };
+                // Nobody receives once the menu is closed
+                if sender.send(Message::Item(item)).is_err() {
+                    return
+                }
+            },
+            Err(e) => {
+                let _ = sender.send(Message::Failed(RumenuError::Io(source.to_string(), e)));
+                return
+            }
+        }
+    }
+    let _ = sender.send(Message::Done);
+}
+
+impl ItemReader {
+    /// Starts reading the items of `path` in `format`, "-" reads the standard input.
+    pub fn spawn(path: &str, format: Format) -> ItemReader {
+        let (sender, receiver) = channel();
+        let path = path.to_string();
+
+        thread::spawn(move || {
+            if path == "-" {
+                let stdin = io::stdin();
+                send_items(stdin.lock(), "standard input", &format, &sender);
+            } else {
+                match File::open(&path) {
+                    Ok(file) => send_items(BufReader::new(file), &path, &format, &sender),
+                    Err(e) => { let _ = sender.send(Message::Failed(RumenuError::Io(path.clone(), e))); }
+                }
+            }
+        });
+
+        ItemReader { receiver: receiver, pending: vec![], done: false }
+    }
+
+    /// Whether every item has been read.
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    /// Blocks until every item is read, `take` returns them afterwards.
+    pub fn wait(&mut self) -> Result<(), RumenuError> {
+        while !self.done {
+            match self.receiver.recv() {
+                Ok(Message::Item(item)) => self.pending.push(item),
+                Ok(Message::Done) | Err(_) => self.done = true,
+                Ok(Message::Failed(e)) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the items read since the last call without waiting for more.
+    pub fn take(&mut self) -> Result<Vec<Item>, RumenuError> {
+        let mut items = mem::take(&mut self.pending);
+        while !self.done {
+            match self.receiver.try_recv() {
+                Ok(Message::Item(item)) => items.push(item),
+                Ok(Message::Done) => self.done = true,
+                Ok(Message::Failed(e)) => return Err(e),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.done = true,
+            }
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use error::RumenuError;
+    use item::{Format, Item};
+    use super::ItemReader;
+
+    fn read_all(reader: &mut ItemReader) -> Result<Vec<Item>, RumenuError> {
+        let mut items = vec![];
+        while !reader.done() {
+            items.extend(reader.take()?);
+        }
+        Ok(items)
+    }
+
+    /// A temporary file of items, removed when dropped.
+    struct ItemsFile(String);
+
+    impl Drop for ItemsFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn write_items(name: &str, content: &[u8]) -> ItemsFile {
+        let path = env::temp_dir().join(format!("rumenu-reader-{}-{}", name, ::std::process::id()));
+        File::create(&path).unwrap().write_all(content).unwrap();
+        ItemsFile(path.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_reads_every_line() {
+        let file = write_items("lines", b"one\ntwo\nthree\n");
+        let mut reader = ItemReader::spawn(&file.0, Format::Lines(None));
+        let items = read_all(&mut reader).unwrap();
+        let labels: Vec<&str> = items.iter().map(|item| &item.label[..]).collect();
+        assert!(labels == vec!["one", "two", "three"]);
+        assert!(reader.done());
+        assert!(reader.take().unwrap().len() == 0);
+    }
+
+    #[test]
+    fn test_wait_reads_every_line() {
+        let file = write_items("wait", b"one\ntwo\n");
+        let mut reader = ItemReader::spawn(&file.0, Format::Lines(None));
+        reader.wait().unwrap();
+        assert!(reader.done());
+        assert!(reader.take().unwrap().len() == 2);
+        assert!(reader.take().unwrap().len() == 0);
+
+        let mut reader = ItemReader::spawn("/nonexistent/rumenu-items", Format::Lines(None));
+        assert!(reader.wait().err().unwrap().exit_code() == 5);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let mut reader = ItemReader::spawn("/nonexistent/rumenu-items", Format::Lines(None));
+        assert!(read_all(&mut reader).err().unwrap().exit_code() == 5);
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let file = write_items("jsonl", b"{\"label\": \"one\"}\n\n{\"label\": \"two\"}\n");
+        let items = read_all(&mut ItemReader::spawn(&file.0, Format::Jsonl)).unwrap();
+        assert!(items.len() == 2 && items[1].label == "two");
+
+        // Bad lines are skipped with a warning
+        let file = write_items("bad-jsonl", b"{\"label\": \"one\"}\n{\"label\": 2}\n{\"label\": \"three\"}\n");
+        let items = read_all(&mut ItemReader::spawn(&file.0, Format::Jsonl)).unwrap();
+        let labels: Vec<&str> = items.iter().map(|item| &item.label[..]).collect();
+        assert!(labels == vec!["one", "three"]);
+    }
+}
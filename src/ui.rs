@@ -1,94 +1,166 @@
-use std::cmp::max;
+use std::cmp::{max, min};
+use std::time::Duration;
 
-use libc::iscntrl;
 use x11::xlib;
-use x11::keysym;
-use xmini::{Display, Window, GC, XFontStruct, Color, KeyPressedEvent};
+use bindings::{Action, Bindings, KeyChord};
+use error::RumenuError;
+use reader::ItemReader;
+use menu::{MenuState, Outcome, View, Rows, Selection, Step, horizontal_page_starts};
+use xmini::{Display, Window, GC, Font, Color, InputContext, KeyPressedEvent, ButtonEvent, Rect, pick_monitor, place};
+
+/// Frames of the spinner shown while the items are being read.
+const SPINNER: &'static [&'static str] = &["|", "/", "-", "\\"];
+
+/// How long to wait for X events before checking for new items.
+const LOADING_TICK: u64 = 100;
+
+/// What a click landed on.
+enum Target {
+    /// The match at this position.
+    Item(usize),
+    PrevPage,
+    NextPage,
+}
 
 pub struct UI {
     x: i32,
     y: i32,
     w: u32,
     h: u32,
-    display: Display,
-    window: Window,
+    /// Width of the border around the window.
+    border: u32,
+    /// Input context, closed before the display.
+    input_context: Option<InputContext>,
     gc: GC,
-    xfont: XFontStruct,
+    xfont: Font,
     colfg: Color,
     colbg: Color,
     selcolfg: Color,
     selcolbg: Color,
-    cursor: usize,
+    hlcolfg: Color,
+    hlcolbg: Color,
+    selhlcolfg: Color,
+    selhlcolbg: Color,
+    input_width: u32,
+    loading: bool,
+    spinner: usize,
+    lines: u32,
+    prompt: String,
+    utf8: xlib::Atom,
+    clipboard: xlib::Atom,
+    /// Chord that closed the menu.
+    close_key: String,
+    /// Window the menu is embedded into.
+    parent: Option<Window>,
+    window: Window,
+    /// Closed last, the X resources above are freed through it.
+    display: Display,
 }
 
 impl UI {
-    pub fn new(settings: &super::Settings) -> UI {
-        let display = Display::new();
+    pub fn new(settings: &super::Settings) -> Result<UI, RumenuError> {
+        let display = Display::new()?;
 
-        display.wait_keyboard();
+        // Embedded menus take the focus once they are shown instead of the keyboard
+        let parent = settings.embed.map(|id| display.window(id));
+        if parent.is_none() {
+            display.wait_keyboard()?;
+        }
 
-        let xfont = display.new_font(&settings.font);
+        let xfont = display.new_font(&settings.fonts)?;
 
         let screen = display.get_default_screen();
         let root = screen.get_root_window();
         let cmap = screen.get_default_colormap();
 
-        let color_fg = display.alloc_named_color(&cmap, &settings.normfgcolor);
-        let color_bg = display.alloc_named_color(&cmap, &settings.normbgcolor);
-        let sel_color_fg = display.alloc_named_color(&cmap, &settings.selfgcolor);
-        let sel_color_bg = display.alloc_named_color(&cmap, &settings.selbgcolor);
+        let color_fg = display.alloc_named_color(&cmap, &settings.normfgcolor)?;
+        let color_bg = display.alloc_named_color(&cmap, &settings.normbgcolor)?;
+        let sel_color_fg = display.alloc_named_color(&cmap, &settings.selfgcolor)?;
+        let sel_color_bg = display.alloc_named_color(&cmap, &settings.selbgcolor)?;
+        let hl_color_fg = display.alloc_named_color(&cmap, &settings.hlfgcolor)?;
+        let hl_color_bg = display.alloc_named_color(&cmap, &settings.hlbgcolor)?;
+        let sel_hl_color_fg = display.alloc_named_color(&cmap, &settings.selhlfgcolor)?;
+        let sel_hl_color_bg = display.alloc_named_color(&cmap, &settings.selhlbgcolor)?;
+        let color_border = display.alloc_named_color(&cmap, &settings.bordercolor)?;
+
+        let line_height = xfont.font_height() + 4;
+        let height = line_height * (settings.lines + 1);
+
+        // An embedded menu is placed inside its parent as if it was a monitor
+        let monitor = match parent {
+            Some(ref parent) => Rect { x: 0, y: 0, ..parent.area() },
+            None => pick_monitor(&screen.monitors(), settings.monitor, screen.focused_area(), screen.pointer_position())
+        };
+        let area = place(monitor, &settings.geometry, settings.topbar, height);
+        let width = area.w;
+
+        let window = parent.as_ref().unwrap_or(&root).new_child_window(area, settings.geometry.border, &color_border, &color_bg);
+
+        display.wait_until_map_notify();
+
+        if let Some(ref parent) = parent {
+            // Take the focus back when the parent or one of its children gets it,
+            // and learn when the parent goes away
+            parent.select_input(xlib::FocusChangeMask | xlib::StructureNotifyMask | xlib::SubstructureNotifyMask);
+            for child in parent.children() {
+                if child.id() != window.id() {
+                    child.select_input(xlib::FocusChangeMask);
+                }
+            }
+            // XEMBED_MAPPED, for parents that speak XEmbed
+            let xembed_info = display.intern_atom("_XEMBED_INFO");
+            window.set_property(xembed_info, xembed_info, &[0, 1]);
+            display.grab_focus(&window)?;
+        }
 
-        let (width, height) = screen.get_geometry(&xfont);
+        // Without the grab clicks outside of the menu are just not seen
+        if settings.grab_pointer {
+            display.wait_pointer(&window)?;
+        }
 
-        let window = root.new_child_window(width, height, &color_bg);
+        let input_context = display.open_input_context(&window);
 
-        display.wait_until_map_notify();
+        let utf8 = display.intern_atom("UTF8_STRING");
+        let clipboard = display.intern_atom("CLIPBOARD");
 
-        UI {
+        Ok(UI {
             x: 0,
             y: 0,
             w: width,
             h: height,
+            border: settings.geometry.border,
+            input_context: input_context,
             display: display,
-            gc: window.new_gc(color_fg.clone(), color_bg.clone()),
+            gc: window.new_gc(&color_fg, &color_bg),
             window: window,
             xfont: xfont,
-            colfg: color_fg.clone(),
-            colbg: color_bg.clone(),
-            selcolfg: sel_color_fg.clone(),
-            selcolbg: sel_color_bg.clone(),
-            cursor: 0,
-        }
+            colfg: color_fg,
+            colbg: color_bg,
+            selcolfg: sel_color_fg,
+            selcolbg: sel_color_bg,
+            hlcolfg: hl_color_fg,
+            hlcolbg: hl_color_bg,
+            selhlcolfg: sel_hl_color_fg,
+            selhlcolbg: sel_hl_color_bg,
+            input_width: 0,
+            loading: false,
+            spinner: 0,
+            lines: settings.lines,
+            prompt: settings.prompt.clone(),
+            utf8: utf8,
+            clipboard: clipboard,
+            close_key: String::new(),
+            parent: parent,
+        })
     }
 
-    fn get_items_page(&self, status: &super::Status) -> (Vec<String>, u32) {
-        let mut current_page = 0;
-
-        // Calculate the space for the words
-        let max_item_length = status.items.iter().fold(0, |acc, item| max(acc, item.len()));
-        let input_width = self.xfont.text_width(&"_".to_string()) as i32 * max_item_length as i32;
-        let mut words_width = self.w as i32;
-        words_width -= 2;
-        words_width -= self.xfont.text_width(&status.settings.prompt) as i32 + 4;
-        words_width -= input_width + 8;
-        words_width -= self.xfont.text_width(&"<".to_string()) as i32 + 4;
-        words_width -= self.xfont.text_width(&">".to_string()) as i32 - 7;
-
-        let mut page_items = vec![];
-        let mut current_x_pos = 0;
-        for item in &status.matches {
-            let item_width = (self.xfont.text_width(&item) + 10) as i32;
-            if current_x_pos + item_width > words_width {
-                current_page += 1;
-                current_x_pos = item_width;
-            } else {
-                current_x_pos += item_width;
-                if current_page == status.page {
-                    page_items.push(item.clone());
-                }
-            }
-        }
-        (page_items, current_page + 1)
+    /// Positions in the match list shown in the current page, and whether
+    /// more pages follow.
+    fn get_items_page(&self, state: &MenuState) -> (Vec<usize>, bool) {
+        let starts = self.page_starts(state, state.selected.unwrap_or(0));
+        let page = min(state.page as usize, starts.len() - 1);
+        let end = if page + 1 < starts.len() { starts[page + 1] } else { state.matches.len() };
+        ((starts[page]..end).collect(), page + 1 < starts.len())
     }
 
     fn draw_bg(&self, x: i32, y: i32, w: u32, h: u32, selected: bool) {
@@ -124,7 +196,7 @@ impl UI {
     fn draw_text(&self, x: i32, y: i32, padding: u32, text: &String, selected: bool) {
         let width = self.xfont.text_width(text);
         let height = self.xfont.text_height() as i32;
-        self.draw_bg(x, y - height, width + padding, y as u32 + 5, selected);
+        self.draw_bg(x, y - height, width + padding, height as u32 + 4, selected);
 
         if selected {
             self.gc.set_foreground(&self.selcolfg);
@@ -133,302 +205,414 @@ impl UI {
             self.gc.set_foreground(&self.colfg);
             self.gc.set_background(&self.colbg);
         }
-        self.gc.set_font(&self.xfont);
-        self.gc.draw_string(x + padding as i32, y, text);
+        let color = if selected { &self.selcolfg } else { &self.colfg };
+        self.gc.draw_string(&self.xfont, color, x + padding as i32, y, text);
         self.display.flush();
     }
 
-    fn draw_horizontal_items(&self, x: i32, status: &super::Status) -> i32 {
+    /// Draws an item like `draw_text` with the byte ranges in `spans` in the
+    /// highlight colors.
+    fn draw_item(&self, x: i32, y: i32, padding: u32, text: &String, spans: &[(usize, usize)], selected: bool) {
+        self.draw_text(x, y, padding, text, selected);
+
+        let height = self.xfont.text_height() as i32;
+        let (fg, bg) = if selected { (&self.selhlcolfg, &self.selhlcolbg) } else { (&self.hlcolfg, &self.hlcolbg) };
+        for &(start, end) in spans {
+            let offset = x + padding as i32 + self.xfont.text_width(&text[..start].to_string()) as i32;
+            let matched = text[start..end].to_string();
+            self.gc.set_foreground(bg);
+            self.gc.fill_rectangle(self.x + offset + 5, self.y + y - height, self.xfont.text_width(&matched), height as u32 + 4);
+            self.gc.draw_string(&self.xfont, fg, offset, y, &matched);
+        }
+        self.display.flush();
+    }
+
+    /// Underlines a marked item drawn with `draw_text`.
+    fn draw_mark(&self, x: i32, y: i32, padding: u32, text: &String, selected: bool) {
+        let width = self.xfont.text_width(text);
+        self.draw_rect(x + padding as i32 + 5, y + 2, width, 1, true, selected);
+    }
+
+    fn draw_horizontal_items(&self, x: i32, state: &MenuState) -> i32 {
         let mut x_pos = x;
 
         // Draw prev icon
-        if status.page > 0 {
+        if state.page > 0 {
             self.draw_text(x_pos, self.xfont.font_height() as i32, 5, &"<".to_string(), false);
             x_pos += self.xfont.text_width(&"<".to_string()) as i32 + 4;
         }
 
         // Draw horizontal matches
-        let (match_items, pages) = self.get_items_page(&status);
+        let (match_items, more_pages) = self.get_items_page(state);
 
-        if pages > status.page + 1 {
+        if more_pages {
             // Draw next icon and break
-            self.draw_text(self.w as i32 - self.xfont.text_width(&">".to_string()) as i32 - 5, self.xfont.font_height() as i32, 5, &">".to_string(), false);
+            let next_x_pos = self.w as i32 - self.loading_width(state) as i32 - self.xfont.text_width(&">".to_string()) as i32 - 5;
+            self.draw_text(next_x_pos, self.xfont.font_height() as i32, 5, &">".to_string(), false);
         }
 
-        for match_item in match_items {
-            self.draw_text(x_pos, self.xfont.font_height() as i32, 5, &match_item, *match_item == status.selected);
-            x_pos += (self.xfont.text_width(&match_item) + 10) as i32;
+        for n in match_items {
+            let id = state.matches[n];
+            let match_item = &state.label(id).to_string();
+            let selected = state.selected == Some(n);
+            self.draw_item(x_pos, self.xfont.font_height() as i32, 5, match_item, &state.highlight(id), selected);
+            if state.marked.contains(&id) {
+                self.draw_mark(x_pos, self.xfont.font_height() as i32, 5, match_item, selected);
+            }
+            x_pos += (self.xfont.text_width(match_item) + 10) as i32;
         }
         x_pos
     }
 
-    fn draw_vertical_items(&self, x: i32, status: &super::Status) -> i32 {
-        unimplemented!();
+    fn draw_vertical_items(&self, x: i32, state: &MenuState) -> i32 {
+        let line_height = self.line_height();
+        let mut y_pos = line_height as i32;
+
+        // Draw one match per row below the input line
+        let (match_items, _) = self.get_items_page(state);
+        for n in match_items {
+            let id = state.matches[n];
+            let match_item = &state.label(id).to_string();
+            let selected = state.selected == Some(n);
+            self.draw_bg(0, y_pos, self.w, line_height, selected);
+            self.draw_item(x, y_pos + self.xfont.font_height() as i32, 5, match_item, &state.highlight(id), selected);
+            if state.marked.contains(&id) {
+                self.draw_mark(x, y_pos + self.xfont.font_height() as i32, 5, match_item, selected);
+            }
+            y_pos += line_height as i32;
+        }
+        y_pos
     }
 
-    fn draw_prompt(&self, x: i32, status: &super::Status) -> i32 {
-        if status.settings.prompt != "" {
-            self.draw_text(x, self.xfont.font_height() as i32, 5, &status.settings.prompt, false);
-            x + self.xfont.text_width(&status.settings.prompt) as i32 + 4
+    fn draw_prompt(&self, x: i32) -> i32 {
+        if self.prompt != "" {
+            self.draw_text(x, self.xfont.font_height() as i32, 5, &self.prompt, false);
+            x + self.xfont.text_width(&self.prompt) as i32 + 4
         } else { x }
     }
 
-    fn draw_input(&self, x: i32, status: &super::Status) -> i32 {
-        let max_item_length = status.items.iter().fold(0, |acc, item| max(acc, item.len()));
-        let input_width = self.xfont.text_width(&"_".to_string()) * max_item_length as u32;
+    /// Width of the input field: the widest item from `start` on, up to a
+    /// third of the window.
+    fn calc_input_width(&self, state: &MenuState, start: usize) -> u32 {
+        let max_item_width = (start..state.items.len()).fold(0, |acc, id| max(acc, self.xfont.text_width(&state.label(id).to_string())));
+        min(max_item_width, self.w / 3)
+    }
 
-        self.draw_text(x, self.xfont.font_height() as i32, 0, &status.text, false);
+    /// Spinner and number of items read so far, while reading.
+    fn loading_text(&self, state: &MenuState) -> Option<String> {
+        if self.loading {
+            Some(format!("{} {}", SPINNER[self.spinner % SPINNER.len()], state.items.len()))
+        } else {
+            None
+        }
+    }
+
+    fn loading_width(&self, state: &MenuState) -> u32 {
+        match self.loading_text(state) {
+            Some(text) => self.xfont.text_width(&text) + 10,
+            None => 0
+        }
+    }
+
+    fn draw_loading(&self, state: &MenuState) {
+        if let Some(text) = self.loading_text(state) {
+            let x_pos = self.w as i32 - self.loading_width(state) as i32;
+            self.draw_text(x_pos, self.xfont.font_height() as i32, 5, &text, false);
+        }
+    }
+
+    fn draw_input(&self, x: i32, state: &MenuState) -> i32 {
+        self.draw_text(x, self.xfont.font_height() as i32, 0, &state.text, false);
 
         // Draw cursor
+        let cursor_x = self.x + x + (self.xfont.text_width(&state.text[0..state.cursor].to_string()) as i32);
         self.gc.set_foreground(&self.colfg);
         self.gc.set_background(&self.colbg);
         self.draw_rect(
-            self.x + x + (self.xfont.text_width(&status.text[0..self.cursor].to_string()) as i32),
+            cursor_x,
             self.y + 4,
             0,
             self.xfont.font_height() - 3,
             false,
             false
         );
+        // The input method shows the text being composed on the baseline there
+        if let Some(ref input_context) = self.input_context {
+            input_context.set_spot(cursor_x, self.y + self.xfont.font_height() as i32);
+        }
         self.display.flush();
 
-        x + (input_width + 8) as i32
+        x + (self.input_width + 8) as i32
 	}
 
 
-    pub fn draw_menu(&self, status: &super::Status) {
+    pub fn draw_menu(&self, state: &MenuState) {
 		let mut x_pos = 2;
         self.draw_bg(0, 0, self.w, self.h, false);
 
-        x_pos = self.draw_prompt(x_pos, &status);
-        x_pos = self.draw_input(x_pos, &status);
+        x_pos = self.draw_prompt(x_pos);
+        let items_x_pos = x_pos;
+        x_pos = self.draw_input(x_pos, state);
 
-        if status.settings.lines > 0 {
-            // Draw vertical matches
-            // TODO
-            self.draw_vertical_items(x_pos, &status);
+        if self.lines > 0 {
+            self.draw_vertical_items(items_x_pos, state);
         } else {
-			self.draw_horizontal_items(x_pos, &status);
+			self.draw_horizontal_items(x_pos, state);
 		}
+        self.draw_loading(state);
+    }
+
+    fn line_height(&self) -> u32 {
+        self.xfont.font_height() + 4
+    }
+
+    /// X where the input starts, after the prompt drawn by `draw_menu`.
+    fn input_x(&self) -> i32 {
+        if self.prompt != "" { 2 + self.xfont.text_width(&self.prompt) as i32 + 4 } else { 2 }
     }
 
-    fn translate_keypress(&mut self, event_state: u32, ksym: u32) -> (u32, u32) {
-        if event_state & xlib::ControlMask != 0 {
-            match ksym {
-                keysym::XK_a => (xlib::ControlMask, keysym::XK_Home),
-                keysym::XK_b => (xlib::ControlMask, keysym::XK_Left),
-                keysym::XK_c => (xlib::ControlMask, keysym::XK_Escape),
-                keysym::XK_d => (xlib::ControlMask, keysym::XK_Delete),
-                keysym::XK_e => (xlib::ControlMask, keysym::XK_End),
-                keysym::XK_f => (xlib::ControlMask, keysym::XK_Right),
-                keysym::XK_h => (xlib::ControlMask, keysym::XK_BackSpace),
-                keysym::XK_i => (xlib::ControlMask, keysym::XK_Tab),
-                keysym::XK_j => (xlib::ControlMask, keysym::XK_Return),
-                keysym::XK_m => (xlib::ControlMask, keysym::XK_Return),
-                keysym::XK_n => (xlib::ControlMask, keysym::XK_Down),
-                keysym::XK_p => (xlib::ControlMask, keysym::XK_Up),
-                _ => (xlib::ControlMask, ksym)
+    /// What is at `x`, `y` in the menu, laid out as `draw_menu` draws it.
+    fn target(&self, x: i32, y: i32, state: &MenuState) -> Option<Target> {
+        let line_height = self.line_height() as i32;
+        let (match_items, more_pages) = self.get_items_page(state);
+
+        if self.lines > 0 {
+            if y < line_height {
+                return None
             }
-        } else if event_state & xlib::Mod1Mask != 0 {
-            match ksym  {
-                keysym::XK_g => (xlib::Mod1Mask, keysym::XK_Home),
-                keysym::XK_G => (xlib::Mod1Mask, keysym::XK_End),
-                keysym::XK_h => (xlib::Mod1Mask, keysym::XK_Up),
-                keysym::XK_j => (xlib::Mod1Mask, keysym::XK_Next),
-                keysym::XK_k => (xlib::Mod1Mask, keysym::XK_Prior),
-                keysym::XK_l => (xlib::Mod1Mask, keysym::XK_Down),
-                _ => (xlib::Mod1Mask, ksym)
+            return match_items.get((y / line_height - 1) as usize).map(|&n| Target::Item(n))
+        }
+
+        let mut x_pos = self.input_x() + self.input_width as i32 + 8;
+        if state.page > 0 {
+            let prev_width = self.xfont.text_width(&"<".to_string()) as i32 + 4;
+            if x >= x_pos && x < x_pos + prev_width {
+                return Some(Target::PrevPage)
             }
-        } else {
-            (0, ksym)
+            x_pos += prev_width;
+        }
+        if more_pages {
+            let next_x_pos = self.w as i32 - self.loading_width(state) as i32 - self.xfont.text_width(&">".to_string()) as i32 - 5;
+            if x >= next_x_pos {
+                return Some(Target::NextPage)
+            }
+        }
+        for n in match_items {
+            let width = (self.xfont.text_width(&state.label(state.matches[n]).to_string()) + 10) as i32;
+            if x >= x_pos && x < x_pos + width {
+                return Some(Target::Item(n))
+            }
+            x_pos += width;
         }
+        None
     }
 
-    fn keypress(&mut self, event: &mut KeyPressedEvent, status: &mut super::Status) -> bool {
-        let old_text = status.text.clone();
+    /// Carries out what an action asked for, gives the outcome when it closes the menu.
+    fn step(&mut self, step: Step, state: &MenuState) -> Option<Outcome> {
+        match step {
+            Step::Continue => self.draw_menu(state),
+            Step::Paste(Selection::Primary) => self.window.convert_selection(xlib::XA_PRIMARY, self.utf8),
+            Step::Paste(Selection::Clipboard) => self.window.convert_selection(self.clipboard, self.utf8),
+            Step::Close(outcome) => return Some(outcome),
+        }
+        None
+    }
 
-        let ksym = self.translate_keypress(event.state(), event.lookup_keysym());
-        let input = event.lookup_string();
+    fn keypress(&mut self, event: &mut KeyPressedEvent, state: &mut MenuState, bindings: &Bindings) -> Option<Outcome> {
+        let step = match bindings.lookup(event.state(), event.lookup_keysym()) {
+            Some(action) => state.handle(action, self),
+            None => {
+                state.insert(&event.lookup_string(self.input_context.as_ref()), self);
+                Step::Continue
+            }
+        };
+        let outcome = self.step(step, state);
+        if outcome.is_some() {
+            self.close_key = KeyChord::new(event.state(), event.lookup_keysym()).name();
+        }
+        outcome
+    }
 
-        match ksym {
-            (xlib::ControlMask, keysym::XK_k) => { status.text.remove(self.cursor); ()},
-            (xlib::ControlMask, keysym::XK_u) => { status.text.remove(self.cursor); ()},
-            (xlib::ControlMask, keysym::XK_w) => {
-                    while self.cursor > 0 && status.text.remove(self.cursor) == ' ' { self.cursor -= 1; }
-                    while self.cursor > 0 && status.text.remove(self.cursor) != ' ' { self.cursor -= 1; }
+    /// Left click accepts an item, or marks it with Ctrl, and turns the
+    /// page on the arrows, middle click pastes and the wheel scrolls rows
+    /// in lists and pages in bars.
+    fn buttonpress(&mut self, event: &ButtonEvent, state: &mut MenuState) -> Option<Outcome> {
+        let (x, y) = event.position();
+        if !outer_rect(self.w, self.h, self.border).contains(x, y) {
+            // Only seen with the pointer grabbed
+            return Some(Outcome::Cancelled)
+        }
+        if !(Rect { x: 0, y: 0, w: self.w, h: self.h }).contains(x, y) {
+            // On the border
+            return None
+        }
+        let step = match event.button() {
+            xlib::Button1 => match self.target(x, y, state) {
+                Some(Target::Item(n)) => {
+                    let action = if event.state() & xlib::ControlMask != 0 { Action::ToggleMark } else { Action::Accept };
+                    state.click(n, action, self)
                 },
-            // TODO: Understand and implement it
-            // (xlib::ControlMask, keysym::XK_y) => {
-            //     unsafe {
-            //         xlib::XConvertSelection(self.display, if (event.state() & xlib::ShiftMask != 0) { clip } else { xlib::XA_PRIMARY }, utf8, utf8, win, xlib::CurrentTime);
-            //     }
-            //     return false;
-            // },
-            (_, keysym::XK_Delete) => {
-                if status.text.len() >= self.cursor {
-                    status.text.remove(self.cursor);
-                }
-            },
-            (_, keysym::XK_BackSpace) => {
-                if self.cursor > 0 && status.text.len() > 0 {
-                    status.text.remove(self.cursor-1);
-                    self.cursor -= 1;
-                }
-            },
-            (_, keysym::XK_End) => {
-                if self.cursor < status.text.len() {
-                    self.cursor = status.text.len();
-                } else {
-                    status.selected = status.matches.last().unwrap_or(&"".to_string()).clone();
-                }
-            },
-            (_, keysym::XK_Escape) => return true,
-            (_, keysym::XK_Home) => {
-                if status.selected == status.matches.first().unwrap_or(&"".to_string()).clone() {
-                    self.cursor = 0;
-                } else {
-                    status.selected = status.matches.first().unwrap_or(&"".to_string()).clone();
-                }
-            },
-            (_, keysym::XK_Left) => {
-                if status.selected == status.matches.first().unwrap_or(&"".to_string()).clone() {
-                    if self.cursor > 0 {
-                        self.cursor -= 1;
-                    }
-                } else if status.settings.lines == 0 {
-                    status.selected = match status.matches.binary_search(&status.selected) {
-                        Ok(n) => status.matches[n - 1].clone(),
-                        Err(_) => "".to_string()
-                    }
-                }
-            },
-            (_, keysym::XK_Up) => {
-                match status.matches.binary_search(&status.selected) {
-                    Ok(0) => return false,
-                    Ok(n) => status.selected = status.matches[n - 1].clone(),
-                    Err(_) => return false
-                }
-            },
-            (_, keysym::XK_Next) => {
-                // TODO: Calc the number of pages
-                status.page += 1
-            },
-            (_, keysym::XK_Prior) => {
-                if status.page > 0 {
-                    status.page -= 1
-                }
-            },
-            (_, keysym::XK_Return) => {
-                if (event.state() & xlib::ShiftMask) != 0 || status.selected == "" {
-                    println!("{}", status.text)
-                } else {
-                    println!("{}", status.selected)
-                }
-                return true
-            },
-            (_, keysym::XK_KP_Enter) => {
-                if (event.state() & xlib::ShiftMask) != 0 || status.selected == "" {
-                    println!("{}", status.text)
-                } else {
-                    println!("{}", status.selected)
-                }
-                return true
-            },
-            (_, keysym::XK_Right) => {
-                if self.cursor < status.text.len() {
-                    self.cursor += 1;
-                } else  {
-                    match status.matches.binary_search(&status.selected) {
-                        Ok(n) => {
-                            if n < (status.matches.len() - 1) {
-                                status.selected = status.matches[n + 1].clone();
-                            } else {
-                                return false
-                            }
-                        },
-                        Err(_) => return false
-                    }
-                }
-            },
-            (_, keysym::XK_Down) => {
-                match status.matches.binary_search(&status.selected) {
-                    Ok(n) => {
-                        if n < (status.matches.len() - 1) {
-                            status.selected = status.matches[n + 1].clone();
-                        } else {
-                            return false
-                        }
-                    },
-                    Err(_) => return false
-                }
-            },
-            (_, keysym::XK_Tab) => {
-                if status.selected != "" {
-                    status.text = status.selected.clone();
-                    self.cursor = status.text.len();
-                }
-            },
-            (_, _) => unsafe {
-                if iscntrl(input.chars().nth(0).unwrap_or(0 as char) as i32) == 0 {
-                    status.text.insert(self.cursor, input.chars().nth(0).unwrap());
-                    self.cursor += 1;
-                }
+                Some(Target::PrevPage) => state.handle(Action::PagePrev, self),
+                Some(Target::NextPage) => state.handle(Action::PageNext, self),
+                None => return None
             },
+            xlib::Button2 => state.handle(Action::PastePrimary, self),
+            xlib::Button4 => state.handle(if self.lines > 0 { Action::SelectPrev } else { Action::PagePrev }, self),
+            xlib::Button5 => state.handle(if self.lines > 0 { Action::SelectNext } else { Action::PageNext }, self),
+            _ => return None
+        };
+        let outcome = self.step(step, state);
+        if outcome.is_some() {
+            self.close_key = format!("Button{}", event.button());
         }
-        if old_text != status.text {
-            if status.settings.matcher == "fuzzy" {
-                status.matches = super::matches::fuzzy_match(&status.text, &status.items);
-            } else if status.settings.matcher == "dmenu" {
-                status.matches = super::matches::dmenu_match(&status.text, &status.items);
-            } else {
-                status.matches = super::matches::simple_match(&status.text, &status.items);
-            }
-            status.page = 0;
-            if !status.matches.contains(&status.selected) {
-                status.selected = status.matches.first().unwrap_or(&"".to_string()).clone()
-            }
+        outcome
+    }
+
+    /// Name of the key chord that closed the menu.
+    pub fn close_key(&self) -> &str {
+        &self.close_key
+    }
+
+    /// Inserts the first line of the converted selection at the cursor.
+    fn paste(&mut self, state: &mut MenuState) {
+        if let Some(text) = self.window.get_text_property(self.utf8) {
+            state.paste(&text, self);
         }
-        self.draw_menu(&status);
-        return false
+        self.draw_menu(state);
     }
 
-    fn paste(&self) {
-        panic!("Not implemented");
+    /// Adds the items read since the last call and turns the spinner.
+    fn load(&mut self, state: &mut MenuState, reader: &mut ItemReader) -> Result<(), RumenuError> {
+        let start = state.items.len();
+        state.add_items(reader.take()?, self);
+        self.input_width = max(self.input_width, self.calc_input_width(state, start));
+        self.loading = !reader.done();
+        self.spinner += 1;
+        self.draw_menu(state);
+        Ok(())
     }
 
-    pub fn run(&mut self, mut status: super::Status) {
-        self.draw_menu(&status);
+    /// Runs the event loop until the menu is closed, adding the items
+    /// from `reader` as they arrive.
+    pub fn run(&mut self, state: &mut MenuState, reader: &mut ItemReader, bindings: &Bindings) -> Result<Outcome, RumenuError> {
+        self.input_width = self.calc_input_width(state, 0);
+        self.load(state, reader)?;
 
         loop {
+            if self.loading {
+                let pending = self.display.wait_event(Duration::from_millis(LOADING_TICK));
+                self.load(state, reader)?;
+                if !pending { continue }
+            }
+            if state.stale() && !self.display.pending() {
+                state.refresh(self);
+                self.draw_menu(state);
+                continue
+            }
             match self.display.next_event() {
                 Some(mut event) => {
-                    if event.filter_event(&mut self.window) { continue; }
+                    if event.filter_event(&self.window) { continue; }
                     match event.get_type() {
                         xlib::Expose => {
                             if event.to_expose_event().count() == 0 {
-                                self.draw_menu(&status);
+                                self.draw_menu(state);
                             }
                         },
                         xlib::KeyPress => {
-                            if self.keypress(&mut event.to_keypress_event(), &mut status) { break }
+                            if let Some(outcome) = self.keypress(&mut event.to_keypress_event(), state, bindings) {
+                                return Ok(outcome)
+                            }
+                        },
+                        xlib::ButtonPress => {
+                            if let Some(outcome) = self.buttonpress(&event.to_button_event(), state) {
+                                return Ok(outcome)
+                            }
                         },
                         xlib::SelectionNotify => {
-                            // if event.to_selection_event().property() == utf8 { <-- This variable
-                            // utf8 must be obtained in any way, review the dmenu code
-                            //     self.paste();
-                            // }
-                            if event.to_selection_event().property() == 0 {
-                                self.paste();
+                            if event.to_selection_event().property() == self.utf8 {
+                                self.paste(state);
                             }
                         },
                         xlib::VisibilityNotify => if event.to_visibility_event().state() != xlib::VisibilityUnobscured {
                             self.window.raise()
                         },
+                        xlib::FocusIn => if event.to_focus_event().window() == self.window.id() {
+                            if let Some(ref input_context) = self.input_context {
+                                input_context.set_focus(true);
+                            }
+                        } else if self.parent.is_some() {
+                            self.display.grab_focus(&self.window)?;
+                        },
+                        xlib::FocusOut => if event.to_focus_event().window() == self.window.id() {
+                            if let Some(ref input_context) = self.input_context {
+                                input_context.set_focus(false);
+                            }
+                        },
+                        xlib::DestroyNotify => if parent_gone(self.parent.as_ref().map(|parent| parent.id()), self.window.id(), event.to_destroy_event().window()) {
+                            return Ok(Outcome::Cancelled)
+                        },
                         _ => continue
                     }
                 },
-                None => break
+                None => return Ok(Outcome::Cancelled)
             }
         }
     }
 }
+
+/// A `w` by `h` window with its `border`, relative to the window content.
+fn outer_rect(w: u32, h: u32, border: u32) -> Rect {
+    Rect { x: -(border as i32), y: -(border as i32), w: w + 2 * border, h: h + 2 * border }
+}
+
+/// Whether the destruction of `destroyed` means the window the menu is
+/// embedded into went away. The menu window is destroyed along with it,
+/// the other children of the parent are not.
+fn parent_gone(parent: Option<xlib::Window>, window: xlib::Window, destroyed: xlib::Window) -> bool {
+    parent.is_some() && (destroyed == window || Some(destroyed) == parent)
+}
+
+impl View for UI {
+    fn page_starts(&self, state: &MenuState, upto: usize) -> Vec<usize> {
+        if self.lines > 0 {
+            return Rows(self.lines as usize).page_starts(state, upto)
+        }
+
+        // Calculate the space for the words
+        let mut words_width = self.w as i32;
+        words_width -= 2;
+        words_width -= self.xfont.text_width(&self.prompt) as i32 + 4;
+        words_width -= self.input_width as i32 + 8;
+        words_width -= self.xfont.text_width(&"<".to_string()) as i32 + 4;
+        words_width -= self.xfont.text_width(&">".to_string()) as i32 - 7;
+        words_width -= self.loading_width(state) as i32;
+
+        let widths = state.matches.iter().map(|&id| (self.xfont.text_width(&state.label(id).to_string()) + 10) as i32);
+        horizontal_page_starts(widths, words_width, upto)
+    }
+
+    fn interrupted(&self) -> bool {
+        self.display.pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{outer_rect, parent_gone};
+
+    #[test]
+    fn test_parent_gone() {
+        assert!(parent_gone(Some(10), 20, 20));
+        assert!(parent_gone(Some(10), 20, 10));
+        assert!(!parent_gone(Some(10), 20, 30));
+        assert!(!parent_gone(None, 20, 20));
+    }
+
+    #[test]
+    fn test_clicks_on_the_border_are_inside() {
+        let window = outer_rect(100, 20, 2);
+        assert!(window.contains(-2, -2) && window.contains(101, 21) && window.contains(50, 10));
+        assert!(!window.contains(-3, 0) && !window.contains(102, 10) && !window.contains(50, 22));
+        assert!(!outer_rect(100, 20, 0).contains(-1, 0));
+    }
+}
@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::ffi::CString;
 use std::str::from_utf8;
 use std::thread::sleep;
@@ -5,11 +6,45 @@ use std::env;
 use std::time::Duration;
 use std::ptr::{null_mut, read};
 use std::mem::zeroed;
+use std::cmp::{max, min};
 use std::convert::From;
 
-use libc::c_uint;
+use libc::{c_uint, c_uchar, c_char, c_int, c_long, c_short, c_ulong, c_ushort, c_void, setlocale, LC_CTYPE, poll, pollfd, POLLIN};
 
 use x11::xlib;
+use x11::xft;
+use x11::xinerama;
+
+use error::RumenuError;
+use x11::xrender;
+
+// Fontconfig calls the x11 crate does not bind, for the glyph fallback
+#[link(name = "fontconfig")]
+extern "C" {
+    fn FcCharSetCreate() -> *mut xft::FcCharSet;
+    fn FcCharSetAddChar(charset: *mut xft::FcCharSet, c: xft::FcChar32) -> c_int;
+    fn FcCharSetDestroy(charset: *mut xft::FcCharSet);
+    fn FcPatternDuplicate(pattern: *const xft::FcPattern) -> *mut xft::FcPattern;
+    fn FcPatternAddCharSet(pattern: *mut xft::FcPattern, object: *const c_char, charset: *const xft::FcCharSet) -> c_int;
+    fn FcPatternAddBool(pattern: *mut xft::FcPattern, object: *const c_char, value: c_int) -> c_int;
+    fn FcPatternDestroy(pattern: *mut xft::FcPattern);
+    fn FcConfigSubstitute(config: *mut c_void, pattern: *mut xft::FcPattern, kind: c_int) -> c_int;
+}
+
+const FC_CHARSET: &'static [u8] = b"charset\0";
+const FC_SCALABLE: &'static [u8] = b"scalable\0";
+const FC_MATCH_PATTERN: c_int = 0;
+
+/// Input styles of an input method, which the x11 crate does not declare.
+#[repr(C)]
+struct XIMStyles {
+    count_styles: c_ushort,
+    supported_styles: *mut c_ulong,
+}
+
+/// Events the menu window listens to.
+const WINDOW_EVENTS: c_long = xlib::StructureNotifyMask | xlib::ExposureMask | xlib::KeyPressMask | xlib::ButtonPressMask |
+                              xlib::VisibilityChangeMask | xlib::FocusChangeMask;
 
 pub struct ColorMap {
     pointer: u64,
@@ -25,7 +60,8 @@ impl Screen {
         unsafe {
             Window {
                 pointer: xlib::XRootWindow(self.display_pointer, self.pointer),
-                display_pointer: self.display_pointer
+                display_pointer: self.display_pointer,
+                foreign: true
             }
         }
     }
@@ -36,22 +72,217 @@ impl Screen {
         }
     }
 
-    pub fn get_geometry(&self, xfont: &XFontStruct) -> (u32, u32) {
+    /// Areas of the Xinerama monitors, or of the whole screen without Xinerama.
+    pub fn monitors(&self) -> Vec<Rect> {
+        let mut monitors = vec![];
+        unsafe {
+            if xinerama::XineramaIsActive(self.display_pointer) != 0 {
+                let mut count: c_int = 0;
+                let info = xinerama::XineramaQueryScreens(self.display_pointer, &mut count);
+                for n in 0..count as isize {
+                    let screen = &*info.offset(n);
+                    monitors.push(Rect { x: screen.x_org as i32, y: screen.y_org as i32, w: screen.width as u32, h: screen.height as u32 });
+                }
+                if info != null_mut() {
+                    xlib::XFree(info as *mut c_void);
+                }
+            }
+            if monitors.is_empty() {
+                monitors.push(Rect {
+                    x: 0,
+                    y: 0,
+                    w: xlib::XDisplayWidth(self.display_pointer, self.pointer) as u32,
+                    h: xlib::XDisplayHeight(self.display_pointer, self.pointer) as u32,
+                });
+            }
+        }
+        monitors
+    }
+
+    /// Area of the top-level window that has the input focus.
+    pub fn focused_area(&self) -> Option<Rect> {
+        unsafe {
+            let root = xlib::XRootWindow(self.display_pointer, self.pointer);
+            let mut window: xlib::Window = 0;
+            let mut revert: c_int = 0;
+            xlib::XGetInputFocus(self.display_pointer, &mut window, &mut revert);
+            if window == root || window == xlib::PointerRoot as xlib::Window || window == 0 {
+                return None
+            }
+
+            // Climb up to the child of the root window
+            let mut top = window;
+            loop {
+                let mut root_return: xlib::Window = 0;
+                let mut parent: xlib::Window = 0;
+                let mut children: *mut xlib::Window = null_mut();
+                let mut count: c_uint = 0;
+                if xlib::XQueryTree(self.display_pointer, top, &mut root_return, &mut parent, &mut children, &mut count) == 0 {
+                    break
+                }
+                if children != null_mut() {
+                    xlib::XFree(children as *mut c_void);
+                }
+                if parent == root || parent == 0 {
+                    break
+                }
+                top = parent;
+            }
+
+            let mut attributes: xlib::XWindowAttributes = zeroed();
+            if xlib::XGetWindowAttributes(self.display_pointer, top, &mut attributes) == 0 {
+                return None
+            }
+            Some(Rect { x: attributes.x, y: attributes.y, w: attributes.width as u32, h: attributes.height as u32 })
+        }
+    }
+
+    /// Position of the pointer on the screen.
+    pub fn pointer_position(&self) -> Option<(i32, i32)> {
         unsafe {
-            let width = xlib::XDisplayWidth(self.display_pointer, self.pointer) as u32;
-            let height = (read(xfont.pointer).max_bounds.ascent + read(xfont.pointer).max_bounds.descent + 4) as u32;
-            (width, height)
+            let root = xlib::XRootWindow(self.display_pointer, self.pointer);
+            let (mut root_return, mut child): (xlib::Window, xlib::Window) = (0, 0);
+            let (mut x, mut y, mut window_x, mut window_y): (c_int, c_int, c_int, c_int) = (0, 0, 0, 0);
+            let mut mask: c_uint = 0;
+            if xlib::XQueryPointer(self.display_pointer, root, &mut root_return, &mut child, &mut x, &mut y,
+                                   &mut window_x, &mut window_y, &mut mask) == 0 {
+                return None
+            }
+            Some((x, y))
+        }
+    }
+}
+
+/// A rectangle in root window coordinates.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Rect {
+    /// Area shared with `other`.
+    pub fn intersection(&self, other: &Rect) -> u64 {
+        let width = min(self.x + self.w as i32, other.x + other.w as i32) - max(self.x, other.x);
+        let height = min(self.y + self.h as i32, other.y + other.h as i32) - max(self.y, other.y);
+        if width > 0 && height > 0 { width as u64 * height as u64 } else { 0 }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.w as i32 && y < self.y + self.h as i32
+    }
+}
+
+/// Monitor to show the menu on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Monitor {
+    /// The one with the focused window, or the pointer when nothing has the focus.
+    Focus,
+    /// The one with the pointer.
+    Pointer,
+    /// The monitor with this number, counting from 0, like dmenu's `-m`.
+    Index(usize),
+}
+
+/// Width of the window, in pixels or in percent of the monitor.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Width {
+    Pixels(u32),
+    Percent(u32),
+}
+
+impl Width {
+    /// Parses "600" or "40%".
+    pub fn parse(width: &str) -> Result<Width, String> {
+        let error = || format!("invalid width '{}'", width);
+        if let Some(percent) = width.strip_suffix('%') {
+            match percent.trim().parse::<u32>() {
+                Ok(percent) if percent > 0 && percent <= 100 => Ok(Width::Percent(percent)),
+                _ => Err(error())
+            }
+        } else {
+            match width.trim().parse::<u32>() {
+                Ok(pixels) if pixels > 0 => Ok(Width::Pixels(pixels)),
+                _ => Err(error())
+            }
+        }
+    }
+}
+
+/// Where the window goes on its monitor.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Geometry {
+    /// Offsets from the monitor edge, or from the centered position.
+    pub x: i32,
+    pub y: i32,
+    /// The whole monitor width by default, half of it when centered.
+    pub width: Option<Width>,
+    pub center: bool,
+    pub border: u32,
+}
+
+/// Area of the window inside its border, for a window with `height`
+/// pixels of content placed on `monitor`.
+pub fn place(monitor: Rect, geometry: &Geometry, topbar: bool, height: u32) -> Rect {
+    let outer_width = match geometry.width {
+        Some(Width::Pixels(width)) => width,
+        Some(Width::Percent(percent)) => monitor.w * percent / 100,
+        None if geometry.center => monitor.w / 2,
+        None => monitor.w.saturating_sub(max(geometry.x, 0) as u32),
+    };
+    let outer_width = min(max(outer_width, geometry.border * 2 + 1), monitor.w);
+    let outer_height = height + geometry.border * 2;
+
+    let (x, y) = if geometry.center {
+        (monitor.x + (monitor.w as i32 - outer_width as i32) / 2 + geometry.x,
+         monitor.y + (monitor.h as i32 - outer_height as i32) / 2 + geometry.y)
+    } else if topbar {
+        (monitor.x + geometry.x, monitor.y + geometry.y)
+    } else {
+        (monitor.x + geometry.x, monitor.y + monitor.h as i32 - outer_height as i32 - geometry.y)
+    };
+    Rect { x: x, y: y, w: outer_width - geometry.border * 2, h: height }
+}
+
+/// Chooses the monitor like dmenu: the numbered one when it exists, or the
+/// one the focused window overlaps the most, or the one with the pointer.
+pub fn pick_monitor(monitors: &[Rect], monitor: Monitor, focus: Option<Rect>, pointer: Option<(i32, i32)>) -> Rect {
+    if let Monitor::Index(n) = monitor {
+        if n < monitors.len() {
+            return monitors[n]
+        }
+    }
+    if monitor != Monitor::Pointer {
+        if let Some(focus) = focus {
+            let best = monitors.iter().max_by_key(|area| area.intersection(&focus));
+            if let Some(best) = best.filter(|area| area.intersection(&focus) > 0) {
+                return *best
+            }
+        }
+    }
+    if let Some((x, y)) = pointer {
+        let point = Rect { x: x, y: y, w: 1, h: 1 };
+        if let Some(area) = monitors.iter().find(|area| area.intersection(&point) > 0) {
+            return *area
         }
     }
+    monitors[0]
 }
 
 pub struct Color {
-    pointer: xlib::XColor
+    pointer: xft::XftColor,
+    display_pointer: *mut xlib::Display,
+    color_map: c_ulong,
 }
 
-impl Clone for Color {
-    fn clone(&self) -> Self {
-        Color { pointer: self.pointer.clone() }
+impl Drop for Color {
+    fn drop(&mut self) {
+        unsafe {
+            let visual = xlib::XDefaultVisual(self.display_pointer, xlib::XDefaultScreen(self.display_pointer));
+            xft::XftColorFree(self.display_pointer, visual, self.color_map, &mut self.pointer);
+        }
     }
 }
 
@@ -71,22 +302,26 @@ impl Drop for Display {
 }
 
 impl Display {
-    pub fn new() -> Display {
-        let display_env;
-        match env::var("DISPLAY") {
-            Ok(val) => display_env = CString::new(val).unwrap(),
-            Err(_) => display_env = CString::new("").unwrap(),
-        }
+    pub fn new() -> Result<Display, RumenuError> {
+        let display_env = match env::var("DISPLAY") {
+            Ok(val) => CString::new(val).unwrap(),
+            Err(_) => CString::new("").unwrap(),
+        };
 
         let display;
         unsafe {
+            // Keyboard input follows the locale of the environment
+            let empty = CString::new("").unwrap();
+            setlocale(LC_CTYPE, empty.as_ptr());
+            // XMODIFIERS picks the input method, like "@im=ibus"
+            xlib::XSetLocaleModifiers(empty.as_ptr());
             display = xlib::XOpenDisplay(display_env.as_ptr());
         }
 
         if display == null_mut() {
-            panic!("Cannot connect to X Server: {}", from_utf8(display_env.as_bytes()).unwrap());
+            return Err(RumenuError::NoDisplay(from_utf8(display_env.as_bytes()).unwrap().to_string()));
         }
-        Display { pointer: display , copy: false }
+        Ok(Display { pointer: display , copy: false })
     }
 
     fn new_from_ptr(ptr: *mut xlib::Display) -> Display {
@@ -97,7 +332,8 @@ impl Display {
         unsafe {
             Window {
                 pointer: xlib::XDefaultRootWindow(self.pointer),
-                display_pointer: self.pointer
+                display_pointer: self.pointer,
+                foreign: true
             }
         }
     }
@@ -108,6 +344,14 @@ impl Display {
         }
     }
 
+    /// Grabs the pointer so that clicks outside of `window` are reported to it too.
+    pub fn grab_pointer(&self, window: &Window) -> bool {
+        unsafe {
+            xlib::XGrabPointer(self.pointer, window.pointer, 1, xlib::ButtonPressMask as c_uint, xlib::GrabModeAsync, xlib::GrabModeAsync,
+                               0, 0, xlib::CurrentTime) == xlib::GrabSuccess
+        }
+    }
+
     pub fn get_default_screen(&self) -> Screen {
         unsafe {
             Screen {
@@ -118,12 +362,23 @@ impl Display {
     }
 
 
-    pub fn alloc_named_color(&self, color_map: &ColorMap, color_name: &String) -> Color {
+    pub fn alloc_named_color(&self, color_map: &ColorMap, color_name: &String) -> Result<Color, RumenuError> {
+        let name = CString::new(color_name.clone())
+            .map_err(|_| RumenuError::BadArguments(format!("invalid color '{}'", color_name.escape_default())))?;
+        unsafe {
+            let mut color: xft::XftColor = zeroed();
+            let visual = xlib::XDefaultVisual(self.pointer, xlib::XDefaultScreen(self.pointer));
+            if xft::XftColorAllocName(self.pointer, visual, color_map.pointer, name.as_ptr(), &mut color) == 0 {
+                return Err(RumenuError::BadArguments(format!("cannot allocate color '{}'", color_name)));
+            }
+            Ok(Color { pointer: color, display_pointer: self.pointer, color_map: color_map.pointer })
+        }
+    }
+
+    pub fn intern_atom(&self, name: &str) -> xlib::Atom {
         unsafe {
-            let color_name = CString::new(color_name.clone()).unwrap();
-            let mut color: xlib::XColor = zeroed();
-            xlib::XAllocNamedColor(self.pointer, color_map.pointer, color_name.as_ptr(), &mut color, &mut color);
-            Color { pointer: color }
+            let name = CString::new(name).unwrap();
+            xlib::XInternAtom(self.pointer, name.as_ptr(), 0)
         }
     }
 
@@ -133,19 +388,21 @@ impl Display {
         }
     }
 
-    pub fn new_window(&self, parent_window: &Window, width: u32, height: u32, color_bg: &Color) -> Window {
+    pub fn new_window(&self, parent_window: &Window, area: Rect, border: u32, color_border: &Color, color_bg: &Color) -> Window {
         unsafe {
             let mut attributes: xlib::XSetWindowAttributes = zeroed();
             attributes.background_pixel = color_bg.pointer.pixel;
+            attributes.border_pixel = color_border.pointer.pixel;
             attributes.override_redirect = 1;
-            attributes.event_mask =  xlib::StructureNotifyMask | xlib::ExposureMask | xlib::KeyPressMask | xlib::VisibilityChangeMask;
+            attributes.event_mask = WINDOW_EVENTS;
 
 
             let window = Window {
-                pointer: xlib::XCreateWindow(self.pointer, parent_window.pointer, 0, 0, width, height, 0, 0,
+                pointer: xlib::XCreateWindow(self.pointer, parent_window.pointer, area.x, area.y, area.w, area.h, border, 0,
                                              xlib::InputOutput as c_uint, null_mut(),
-                                             xlib::CWOverrideRedirect | xlib::CWBackPixel | xlib::CWEventMask, &mut attributes),
-                display_pointer: self.pointer
+                                             xlib::CWOverrideRedirect | xlib::CWBackPixel | xlib::CWBorderPixel | xlib::CWEventMask, &mut attributes),
+                display_pointer: self.pointer,
+                foreign: false
             };
 
             // Show window
@@ -155,13 +412,28 @@ impl Display {
         }
     }
 
-    pub fn new_font(&self, font_name: &String) -> XFontStruct {
-        unsafe{
-            let fontstr = CString::new(font_name.clone()).unwrap();
-            XFontStruct {
-                pointer: xlib::XLoadQueryFont(self.pointer, fontstr.as_ptr())
+    /// Opens the Xft fonts matching the fontconfig patterns, the first one is
+    /// the primary font and the rest are used for the glyphs it lacks.
+    pub fn new_font(&self, font_names: &Vec<String>) -> Result<Font, RumenuError> {
+        let mut fonts = vec![];
+        let mut pattern = null_mut();
+        for font_name in font_names {
+            let fontstr = CString::new(font_name.clone())
+                .map_err(|_| RumenuError::BadArguments(format!("invalid font '{}'", font_name.escape_default())))?;
+            let font = unsafe {
+                xft::XftFontOpenName(self.pointer, xlib::XDefaultScreen(self.pointer), fontstr.as_ptr())
+            };
+            if font != null_mut() {
+                if fonts.len() == 0 {
+                    pattern = unsafe { xft::XftNameParse(fontstr.as_ptr()) };
+                }
+                fonts.push(font);
             }
         }
+        if fonts.len() == 0 {
+            return Err(RumenuError::BadArguments(format!("cannot load font: {}", font_names.join(", "))));
+        }
+        Ok(Font { fonts: RefCell::new(fonts), pattern: pattern, missing: RefCell::new(vec![]), display_pointer: self.pointer })
     }
 
     pub fn sync(&self, discard: bool) {
@@ -174,26 +446,123 @@ impl Display {
         }
     }
 
-    pub fn wait_keyboard(&self) {
+    pub fn wait_keyboard(&self) -> Result<(), RumenuError> {
         /* try to grab keyboard, we may have to wait for another process to ungrab */
         for _ in 1..1000 {
             if self.grab_keyboard() {
-                return
+                return Ok(())
             }
             sleep(Duration::new(1, 0));
         }
-        panic!("cannot grab keyboard");
+        Err(RumenuError::GrabFailed)
+    }
+
+    /// Grabs the pointer for `window`, retrying for a second like dmenu does
+    /// for the keyboard while another client releases it.
+    pub fn wait_pointer(&self, window: &Window) -> Result<(), RumenuError> {
+        for _ in 0..1000 {
+            if self.grab_pointer(window) {
+                return Ok(())
+            }
+            sleep(Duration::from_millis(1));
+        }
+        Err(RumenuError::PointerGrabFailed)
+    }
+
+    /// A window of another client, like the one to embed the menu into.
+    pub fn window(&self, id: xlib::Window) -> Window {
+        Window { pointer: id, display_pointer: self.pointer, foreign: true }
+    }
+
+    /// Gives the input focus to `window`, used instead of grabbing the
+    /// keyboard when embedded. Other clients may take a while to let it go.
+    pub fn grab_focus(&self, window: &Window) -> Result<(), RumenuError> {
+        for _ in 0..100 {
+            let mut focused: xlib::Window = 0;
+            let mut revert: c_int = 0;
+            unsafe {
+                xlib::XGetInputFocus(self.pointer, &mut focused, &mut revert);
+                if focused == window.pointer {
+                    return Ok(())
+                }
+                xlib::XSetInputFocus(self.pointer, window.pointer, xlib::RevertToParent, xlib::CurrentTime);
+            }
+            sleep(Duration::from_millis(10));
+        }
+        Err(RumenuError::FocusFailed)
+    }
+
+    /// Opens the input method and an input context on `window`, None when
+    /// there is no input method and keys are looked up without one. The
+    /// preedit text is shown at the spot given to `set_spot` when the input
+    /// method supports it, in a window of its own otherwise.
+    pub fn open_input_context(&self, window: &Window) -> Option<InputContext> {
+        unsafe {
+            let im = xlib::XOpenIM(self.pointer, null_mut(), null_mut(), null_mut());
+            if im == null_mut() {
+                return None
+            }
+            let at_spot = (xlib::XIMPreeditPosition | xlib::XIMStatusNothing) as c_ulong;
+            let mut ic = null_mut();
+            if supports_style(im, at_spot) {
+                let mut spot = xlib::XPoint { x: 0, y: 0 };
+                let attributes = xlib::XVaCreateNestedList(0, xlib::XNSpotLocation_0.as_ptr(), &mut spot, null_mut::<c_void>());
+                ic = xlib::XCreateIC(im, xlib::XNInputStyle_0.as_ptr(), at_spot,
+                                     xlib::XNClientWindow_0.as_ptr(), window.pointer,
+                                     xlib::XNFocusWindow_0.as_ptr(), window.pointer,
+                                     xlib::XNPreeditAttributes_0.as_ptr(), attributes, null_mut::<c_void>());
+                xlib::XFree(attributes);
+            }
+            let spot = ic != null_mut();
+            if !spot {
+                let style = (xlib::XIMPreeditNothing | xlib::XIMStatusNothing) as c_ulong;
+                ic = xlib::XCreateIC(im, xlib::XNInputStyle_0.as_ptr(), style,
+                                     xlib::XNClientWindow_0.as_ptr(), window.pointer,
+                                     xlib::XNFocusWindow_0.as_ptr(), window.pointer, null_mut::<c_void>());
+            }
+            if ic == null_mut() {
+                xlib::XCloseIM(im);
+                return None
+            }
+
+            // The input method may need to see more events than the window asks for
+            let mut filter_events: c_ulong = 0;
+            xlib::XGetICValues(ic, xlib::XNFilterEvents_0.as_ptr(), &mut filter_events, null_mut::<c_void>());
+            window.select_input(WINDOW_EVENTS | filter_events as c_long);
+
+            let input_context = InputContext { im: im, ic: ic, spot: spot };
+            input_context.set_focus(true);
+            Some(input_context)
+        }
     }
 
     pub fn wait_until_map_notify(&self) {
         loop {
             match self.next_event() {
-                Some(event) => if event.get_type() == xlib::MapNotify { break },
-                None => ()
+                Some(ref event) if event.get_type() == xlib::MapNotify => break,
+                _ => ()
             }
         }
     }
 
+    /// Whether an event is waiting to be read.
+    pub fn pending(&self) -> bool {
+        unsafe { xlib::XPending(self.pointer) > 0 }
+    }
+
+    /// Waits up to `timeout` for an event, returns whether one is pending.
+    pub fn wait_event(&self, timeout: Duration) -> bool {
+        if self.pending() {
+            return true
+        }
+        unsafe {
+            let mut fd = pollfd { fd: xlib::XConnectionNumber(self.pointer), events: POLLIN, revents: 0 };
+            let millis = timeout.as_secs() as c_int * 1000 + timeout.subsec_nanos() as c_int / 1000000;
+            poll(&mut fd, 1, millis);
+        }
+        self.pending()
+    }
+
     pub fn next_event(&self) -> Option<Event> {
         unsafe {
             let mut event: xlib::XEvent = zeroed();
@@ -203,43 +572,155 @@ impl Display {
     }
 }
 
+/// An input context, which turns key presses into text with dead keys,
+/// compose sequences and input methods like IBus or Fcitx.
+pub struct InputContext {
+    im: xlib::XIM,
+    ic: xlib::XIC,
+    /// Whether the preedit text is drawn at a spot of the window.
+    spot: bool,
+}
+
+/// Whether the input method `im` supports the input `style`.
+unsafe fn supports_style(im: xlib::XIM, style: c_ulong) -> bool {
+    let mut styles: *mut XIMStyles = null_mut();
+    if xlib::XGetIMValues(im, xlib::XNQueryInputStyle_0.as_ptr(), &mut styles, null_mut::<c_void>()) != null_mut() || styles == null_mut() {
+        return false
+    }
+    let supported = ::std::slice::from_raw_parts((*styles).supported_styles, (*styles).count_styles as usize).contains(&style);
+    xlib::XFree(styles as *mut c_void);
+    supported
+}
+
+impl Drop for InputContext {
+    fn drop(&mut self) {
+        unsafe {
+            xlib::XDestroyIC(self.ic);
+            xlib::XCloseIM(self.im);
+        }
+    }
+}
+
+impl InputContext {
+    /// Moves the preedit text to the point `x`, `y` of the window, where
+    /// the cursor is.
+    pub fn set_spot(&self, x: i32, y: i32) {
+        if !self.spot {
+            return
+        }
+        unsafe {
+            let mut spot = xlib::XPoint { x: x as c_short, y: y as c_short };
+            let attributes = xlib::XVaCreateNestedList(0, xlib::XNSpotLocation_0.as_ptr(), &mut spot, null_mut::<c_void>());
+            xlib::XSetICValues(self.ic, xlib::XNPreeditAttributes_0.as_ptr(), attributes, null_mut::<c_void>());
+            xlib::XFree(attributes);
+        }
+    }
+
+    /// Tells the input method whether the window has the keyboard focus,
+    /// so that it shows or hides its preedit window.
+    pub fn set_focus(&self, focused: bool) {
+        unsafe {
+            if focused {
+                xlib::XSetICFocus(self.ic);
+            } else {
+                xlib::XUnsetICFocus(self.ic);
+            }
+        }
+    }
+}
+
 pub struct Window {
     pointer: xlib::Window,
     display_pointer: *mut xlib::Display,
+    /// Windows rumenu did not create are not destroyed with it.
+    foreign: bool,
 }
 
 impl Drop for Window {
     fn drop(&mut self) {
-        unsafe {
-            xlib::XDestroyWindow(self.display_pointer, self.pointer);
+        if !self.foreign {
+            unsafe {
+                xlib::XDestroyWindow(self.display_pointer, self.pointer);
+            }
         }
     }
 }
 
 impl Window {
+    pub fn id(&self) -> xlib::Window {
+        self.pointer
+    }
+
+    /// Position inside the parent window and size.
+    pub fn area(&self) -> Rect {
+        unsafe {
+            let mut attributes: xlib::XWindowAttributes = zeroed();
+            xlib::XGetWindowAttributes(self.display_pointer, self.pointer, &mut attributes);
+            Rect { x: attributes.x, y: attributes.y, w: attributes.width as u32, h: attributes.height as u32 }
+        }
+    }
+
+    pub fn children(&self) -> Vec<Window> {
+        unsafe {
+            let (mut root, mut parent): (xlib::Window, xlib::Window) = (0, 0);
+            let mut children: *mut xlib::Window = null_mut();
+            let mut count: c_uint = 0;
+            if xlib::XQueryTree(self.display_pointer, self.pointer, &mut root, &mut parent, &mut children, &mut count) == 0 {
+                return vec![]
+            }
+            if children == null_mut() {
+                return vec![]
+            }
+            let windows = ::std::slice::from_raw_parts(children, count as usize).iter()
+                .map(|&child| Window { pointer: child, display_pointer: self.display_pointer, foreign: true })
+                .collect();
+            xlib::XFree(children as *mut c_void);
+            windows
+        }
+    }
+
+    /// Adds `mask` to the events of the window this client receives.
+    pub fn select_input(&self, mask: c_long) {
+        unsafe {
+            xlib::XSelectInput(self.display_pointer, self.pointer, mask);
+        }
+    }
+
+    /// Replaces a property made of 32 bit values.
+    pub fn set_property(&self, property: xlib::Atom, kind: xlib::Atom, values: &[c_long]) {
+        unsafe {
+            xlib::XChangeProperty(self.display_pointer, self.pointer, property, kind, 32, xlib::PropModeReplace,
+                                  values.as_ptr() as *const c_uchar, values.len() as c_int);
+        }
+    }
+
     pub fn map(&self) {
         unsafe {
             xlib::XMapWindow(self.display_pointer, self.pointer);
         }
     }
 
-    pub fn new_child_window(&self, width: u32, height: u32, color_bg: &Color) -> Window {
-        Display::new_from_ptr(self.display_pointer).new_window(self, width, height, color_bg)
+    pub fn new_child_window(&self, area: Rect, border: u32, color_border: &Color, color_bg: &Color) -> Window {
+        Display::new_from_ptr(self.display_pointer).new_window(self, area, border, color_border, color_bg)
     }
 
-    pub fn new_gc(&self, color_fg: Color, color_bg: Color) -> GC {
+    pub fn new_gc(&self, color_fg: &Color, color_bg: &Color) -> GC {
         unsafe {
             let mut values: xlib::XGCValues = zeroed();
-            let valuesmask: u64 = 0 as u64;
+            let valuesmask: u64 = 0;
 
+            let screen = xlib::XDefaultScreen(self.display_pointer);
             let gc = GC {
                 pointer: xlib::XCreateGC(self.display_pointer, self.pointer, valuesmask, &mut values),
+                draw: xft::XftDrawCreate(self.display_pointer, self.pointer,
+                                         xlib::XDefaultVisual(self.display_pointer, screen),
+                                         xlib::XDefaultColormap(self.display_pointer, screen)),
                 display_pointer: self.display_pointer,
                 window_pointer: self.pointer
             };
 
-            gc.set_foreground(&color_fg);
-            gc.set_background(&color_bg);
+            gc.set_foreground(color_fg);
+            gc.set_background(color_bg);
 
             gc.set_line_attributes(1, xlib::LineSolid, xlib::CapButt, xlib::JoinMiter);
             gc.set_fill_style(xlib::FillSolid);
@@ -255,26 +736,64 @@ impl Window {
             xlib::XRaiseWindow(self.display_pointer, self.pointer);
         }
     }
+
+    /// Asks the owner of `selection` to store it converted to `target` in the
+    /// `target` property of this window, a SelectionNotify event follows.
+    pub fn convert_selection(&self, selection: xlib::Atom, target: xlib::Atom) {
+        unsafe {
+            xlib::XConvertSelection(self.display_pointer, selection, target, target, self.pointer, xlib::CurrentTime);
+        }
+    }
+
+    /// Reads a text property of the window as UTF-8 and deletes it.
+    pub fn get_text_property(&self, property: xlib::Atom) -> Option<String> {
+        unsafe {
+            let mut actual_type: xlib::Atom = 0;
+            let mut actual_format: c_int = 0;
+            let mut nitems: c_ulong = 0;
+            let mut bytes_after: c_ulong = 0;
+            let mut data: *mut c_uchar = null_mut();
+
+            let result = xlib::XGetWindowProperty(self.display_pointer, self.pointer, property, 0, c_long::MAX / 4, 1,
+                                                  xlib::AnyPropertyType as xlib::Atom, &mut actual_type, &mut actual_format,
+                                                  &mut nitems, &mut bytes_after, &mut data);
+            if result != xlib::Success as c_int || data == null_mut() {
+                return None
+            }
+            let bytes = ::std::slice::from_raw_parts(data, nitems as usize);
+            let text = String::from_utf8_lossy(bytes).into_owned();
+            xlib::XFree(data as *mut c_void);
+            Some(text)
+        }
+    }
 }
 
 pub struct GC {
     pointer: xlib::GC,
+    draw: *mut xft::XftDraw,
     display_pointer: *mut xlib::Display,
     window_pointer: xlib::Window
 }
 
+impl Drop for GC {
+    fn drop(&mut self) {
+        unsafe {
+            xft::XftDrawDestroy(self.draw);
+            xlib::XFreeGC(self.display_pointer, self.pointer);
+        }
+    }
+}
+
 impl GC {
     pub fn set_background(&self, color: &Color) {
         unsafe {
             xlib::XSetBackground(self.display_pointer, self.pointer, color.pointer.pixel);
         }
-        ()
     }
     pub fn set_foreground(&self, color: &Color) {
         unsafe {
             xlib::XSetForeground(self.display_pointer, self.pointer, color.pointer.pixel);
         }
-        ()
     }
     pub fn fill_rectangle(&self, x: i32, y: i32, w: u32, h: u32) {
         unsafe {
@@ -297,41 +816,143 @@ impl GC {
         }
     }
 
-    pub fn set_font(&self, font: &XFontStruct) {
-        unsafe {
-            xlib::XSetFont(self.display_pointer, self.pointer, read(font.pointer).fid);
+    pub fn draw_string(&self, font: &Font, color: &Color, x: i32, y: i32, text: &String) {
+        let mut x_pos = x + 5;
+        for (xftfont, run) in font.runs(text) {
+            unsafe {
+                xft::XftDrawStringUtf8(self.draw, &color.pointer, xftfont, x_pos, y, run.as_ptr() as *const c_uchar, run.len() as i32);
+            }
+            x_pos += font.run_width(xftfont, run) as i32;
         }
     }
+}
+
+/// Xft fonts loaded from fontconfig patterns, the first one is the primary
+/// font and the others are fallbacks for missing glyphs. When none of them
+/// has a glyph, fontconfig is asked for a font like the primary one that has
+/// it, like dmenu does.
+pub struct Font {
+    fonts: RefCell<Vec<*mut xft::XftFont>>,
+    /// Pattern of the primary font, null if it cannot be parsed.
+    pattern: *mut xft::FcPattern,
+    /// Characters fontconfig found no font for.
+    missing: RefCell<Vec<char>>,
+    display_pointer: *mut xlib::Display,
+}
 
-    pub fn draw_string(&self, x: i32, y: i32, text: &String) {
+impl Drop for Font {
+    fn drop(&mut self) {
         unsafe {
-            xlib::XDrawString(self.display_pointer, self.window_pointer, self.pointer, x + 5, y, CString::new(text.clone()).unwrap().as_ptr(), text.len() as i32);
+            for &font in self.fonts.borrow().iter() {
+                xft::XftFontClose(self.display_pointer, font);
+            }
+            if self.pattern != null_mut() {
+                FcPatternDestroy(self.pattern);
+            }
         }
     }
 }
 
-pub struct XFontStruct {
-    pointer: *mut xlib::XFontStruct,
-}
+impl Font {
+    fn primary(&self) -> *mut xft::XftFont {
+        self.fonts.borrow()[0]
+    }
 
-impl XFontStruct {
-    pub fn text_width(&self, text: &String) -> u32 {
-        text.len() as u32 * self.font_width()
+    /// Splits the text in runs of characters drawn with the same font.
+    fn runs<'a>(&self, text: &'a str) -> Vec<(*mut xft::XftFont, &'a str)> {
+        let mut runs = vec![];
+        let mut start = 0;
+        let mut current = self.primary();
+        for (position, c) in text.char_indices() {
+            let font = self.font_for(c);
+            if font != current {
+                if position > start {
+                    runs.push((current, &text[start..position]));
+                }
+                start = position;
+                current = font;
+            }
+        }
+        if text.len() > start {
+            runs.push((current, &text[start..]));
+        }
+        runs
     }
 
-    pub fn text_height(&self) -> u32 {
-        self.font_height()
+    fn font_for(&self, c: char) -> *mut xft::XftFont {
+        for &font in self.fonts.borrow().iter() {
+            if unsafe { xft::XftCharExists(self.display_pointer, font, c as u32) } != 0 {
+                return font
+            }
+        }
+        if self.pattern == null_mut() || self.missing.borrow().contains(&c) {
+            return self.primary()
+        }
+        match self.match_font(c) {
+            Some(font) => {
+                self.fonts.borrow_mut().push(font);
+                font
+            },
+            None => {
+                self.missing.borrow_mut().push(c);
+                self.primary()
+            }
+        }
     }
 
-    pub fn font_width(&self) -> u32 {
-        unsafe{
-            (read(self.pointer).max_bounds.rbearing - read(self.pointer).min_bounds.lbearing) as u32
+    /// Opens the font fontconfig picks for the primary pattern with `c` added.
+    fn match_font(&self, c: char) -> Option<*mut xft::XftFont> {
+        unsafe {
+            let screen = xlib::XDefaultScreen(self.display_pointer);
+            let charset = FcCharSetCreate();
+            FcCharSetAddChar(charset, c as xft::FcChar32);
+            let pattern = FcPatternDuplicate(self.pattern);
+            FcPatternAddCharSet(pattern, FC_CHARSET.as_ptr() as *const c_char, charset);
+            FcPatternAddBool(pattern, FC_SCALABLE.as_ptr() as *const c_char, 1);
+            FcConfigSubstitute(null_mut(), pattern, FC_MATCH_PATTERN);
+            xft::XftDefaultSubstitute(self.display_pointer, screen, pattern);
+            let mut result = xft::FcResult::NoMatch;
+            let matched = xft::XftFontMatch(self.display_pointer, screen, pattern, &mut result);
+            FcCharSetDestroy(charset);
+            FcPatternDestroy(pattern);
+            if matched == null_mut() {
+                return None
+            }
+            // The font owns the matched pattern once it is open
+            let font = xft::XftFontOpenPattern(self.display_pointer, matched);
+            if font == null_mut() {
+                FcPatternDestroy(matched);
+                return None
+            }
+            if xft::XftCharExists(self.display_pointer, font, c as u32) == 0 {
+                xft::XftFontClose(self.display_pointer, font);
+                return None
+            }
+            Some(font)
         }
     }
 
+    fn run_width(&self, font: *mut xft::XftFont, run: &str) -> u32 {
+        unsafe {
+            let mut extents: xrender::XGlyphInfo = zeroed();
+            xft::XftTextExtentsUtf8(self.display_pointer, font, run.as_ptr() as *const c_uchar, run.len() as i32, &mut extents);
+            extents.xOff as u32
+        }
+    }
+
+    /// Advance width of the text in pixels.
+    pub fn text_width(&self, text: &String) -> u32 {
+        self.runs(text).iter().fold(0, |acc, &(font, run)| acc + self.run_width(font, run))
+    }
+
+    pub fn text_height(&self) -> u32 {
+        self.font_height()
+    }
+
     pub fn font_height(&self) -> u32 {
-        unsafe{
-            (read(self.pointer).max_bounds.ascent + read(self.pointer).max_bounds.descent) as u32
+        unsafe {
+            let font = read(self.primary());
+            (font.ascent + font.descent) as u32
         }
     }
 }
@@ -355,19 +976,36 @@ impl KeyPressedEvent {
         unsafe { xlib::XLookupKeysym(&mut self.pointer, 0) as u32 }
     }
 
-    pub fn lookup_string(&mut self) -> String {
-        let mut buf = [0 as i8; 32];
-        let mut buf_u8 = [0 as u8; 32];
-
-        unsafe {
-            xlib::XLookupString(&mut self.pointer, buf.as_mut_ptr(), buf.len() as i32, null_mut(), null_mut());
+    /// Text typed with the key, as composed by the input context when there is one.
+    pub fn lookup_string(&mut self, input_context: Option<&InputContext>) -> String {
+        let ic = match input_context {
+            Some(input_context) => input_context.ic,
+            None => return self.lookup_latin1()
+        };
+        let mut buf: Vec<u8> = vec![0; 64];
+        loop {
+            let mut status: c_int = 0;
+            let len = unsafe {
+                xlib::Xutf8LookupString(ic, &mut self.pointer, buf.as_mut_ptr() as *mut c_char, buf.len() as c_int, null_mut(), &mut status)
+            };
+            match status {
+                // The length is the size the text needs
+                xlib::XBufferOverflow => buf.resize(len as usize, 0),
+                xlib::XLookupChars | xlib::XLookupBoth => return String::from_utf8_lossy(&buf[..len as usize]).into_owned(),
+                _ => return String::new()
+            }
         }
+    }
 
-        for x in 0..32 {
-            buf_u8[x] = buf[x] as u8;
-        }
+    fn lookup_latin1(&mut self) -> String {
+        let mut buf = [0 as c_char; 32];
 
-        String::from_utf8_lossy(&buf_u8).into_owned()
+        let len = unsafe {
+            xlib::XLookupString(&mut self.pointer, buf.as_mut_ptr(), buf.len() as i32, null_mut(), null_mut())
+        };
+
+        // XLookupString returns Latin-1 text
+        buf[..len as usize].iter().map(|&b| b as u8 as char).collect()
     }
 }
 
@@ -383,6 +1021,29 @@ impl VisibilityEvent {
     pub fn state(&self) -> i32 { self.pointer.state }
 }
 
+pub struct ButtonEvent { pointer: xlib::XButtonEvent }
+
+impl ButtonEvent {
+    pub fn button(&self) -> u32 { self.pointer.button }
+
+    pub fn state(&self) -> u32 { self.pointer.state }
+
+    /// Position relative to the window that got the event.
+    pub fn position(&self) -> (i32, i32) { (self.pointer.x, self.pointer.y) }
+}
+
+pub struct FocusEvent { pointer: xlib::XFocusChangeEvent }
+
+impl FocusEvent {
+    pub fn window(&self) -> xlib::Window { self.pointer.window }
+}
+
+pub struct DestroyEvent { pointer: xlib::XDestroyWindowEvent }
+
+impl DestroyEvent {
+    pub fn window(&self) -> xlib::Window { self.pointer.window }
+}
+
 impl Event {
     pub fn filter_event(&mut self, window: &Window) -> bool {
         unsafe {
@@ -409,4 +1070,87 @@ impl Event {
     pub fn to_visibility_event(&self) -> VisibilityEvent {
         VisibilityEvent { pointer: xlib::XVisibilityEvent::from(self.pointer) }
     }
+
+    pub fn to_button_event(&self) -> ButtonEvent {
+        ButtonEvent { pointer: xlib::XButtonEvent::from(self.pointer) }
+    }
+
+    pub fn to_focus_event(&self) -> FocusEvent {
+        FocusEvent { pointer: xlib::XFocusChangeEvent::from(self.pointer) }
+    }
+
+    pub fn to_destroy_event(&self) -> DestroyEvent {
+        DestroyEvent { pointer: xlib::XDestroyWindowEvent::from(self.pointer) }
+    }
+}
+
+/// Parses a window id in decimal or in hexadecimal with "0x", as `xwininfo` prints them.
+pub fn parse_window_id(id: &str) -> Result<xlib::Window, String> {
+    let id = id.trim();
+    let parsed = if id.starts_with("0x") || id.starts_with("0X") {
+        xlib::Window::from_str_radix(&id[2..], 16)
+    } else {
+        id.parse::<xlib::Window>()
+    };
+    match parsed {
+        Ok(window) if window != 0 => Ok(window),
+        _ => Err(format!("invalid window id '{}'", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_window_id, pick_monitor, place, Geometry, Monitor, Rect, Width};
+
+    fn rect(x: i32, y: i32, w: u32, h: u32) -> Rect {
+        Rect { x: x, y: y, w: w, h: h }
+    }
+
+    #[test]
+    fn test_pick_monitor() {
+        let monitors = [rect(0, 0, 1920, 1080), rect(1920, 0, 1280, 1024)];
+        let focus = Some(rect(1800, 100, 400, 300));
+        let pointer = Some((100, 100));
+        assert!(pick_monitor(&monitors, Monitor::Index(0), focus, pointer) == monitors[0]);
+        assert!(pick_monitor(&monitors, Monitor::Focus, focus, pointer) == monitors[1]);
+        assert!(pick_monitor(&monitors, Monitor::Pointer, focus, pointer) == monitors[0]);
+        // A missing monitor falls back to the focus, no focus to the pointer
+        assert!(pick_monitor(&monitors, Monitor::Index(5), focus, pointer) == monitors[1]);
+        assert!(pick_monitor(&monitors, Monitor::Focus, None, Some((2000, 10))) == monitors[1]);
+        assert!(pick_monitor(&monitors, Monitor::Focus, Some(rect(-500, 0, 100, 100)), None) == monitors[0]);
+    }
+
+    #[test]
+    fn test_parse_width() {
+        assert!(Width::parse("600") == Ok(Width::Pixels(600)));
+        assert!(Width::parse("40%") == Ok(Width::Percent(40)));
+        for width in ["0", "120%", "-5", "wide", "%"].iter() {
+            assert!(Width::parse(width) == Err(format!("invalid width '{}'", width)));
+        }
+    }
+
+    #[test]
+    fn test_place() {
+        let monitor = rect(1920, 0, 1280, 1024);
+        let bar = Geometry { x: 0, y: 0, width: None, center: false, border: 0 };
+        assert!(place(monitor, &bar, true, 20) == rect(1920, 0, 1280, 20));
+        assert!(place(monitor, &bar, false, 20) == rect(1920, 1004, 1280, 20));
+
+        let offset = Geometry { x: 100, y: 10, width: None, border: 2, ..bar };
+        assert!(place(monitor, &offset, true, 20) == rect(2020, 10, 1176, 20));
+        assert!(place(monitor, &offset, false, 20) == rect(2020, 990, 1176, 20));
+
+        let popup = Geometry { center: true, width: Some(Width::Percent(50)), border: 2, ..bar };
+        assert!(place(monitor, &popup, true, 96) == rect(2240, 462, 636, 96));
+        let wide = Geometry { width: Some(Width::Pixels(5000)), ..popup };
+        assert!(place(monitor, &wide, false, 96) == rect(1920, 462, 1276, 96));
+    }
+
+    #[test]
+    fn test_parse_window_id() {
+        assert!(parse_window_id("0x1e00003") == Ok(0x1e00003));
+        assert!(parse_window_id("31457283") == Ok(31457283));
+        assert!(parse_window_id("0").is_err());
+        assert!(parse_window_id("0xzz").is_err());
+    }
 }